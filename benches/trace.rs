@@ -0,0 +1,339 @@
+// Timings for the intersection functions, single camera rays and whole
+// frames. Run with `cargo bench`, or `cargo bench -- <filter>` to only run the
+// benchmarks whose names contain the filter.
+//
+// `cargo bench -- --save-baseline <file>` keeps the timings to compare later
+// runs against with `cargo bench -- --baseline <file>`. Changes bigger than
+// the noise between samples are called out as regressions or improvements.
+
+use cast::camera::Camera;
+use cast::generate::{generate, Generator};
+use cast::geometry::{
+    closest_hit, closest_hits, ray_intersects_cuboid, ray_intersects_sphere, ray_intersects_torus,
+    ray_intersects_triangle, RayPacket, LANES,
+};
+use cast::render::{render_image, trace_view, Renderer};
+use cast::scene::{default_scene, Cuboid, Object, Scene, Sphere, Torus, Triangle};
+use cast::settings::{RenderMode, Settings};
+use notan::math::{Mat3, Vec3};
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// Each benchmark takes this many samples, each running for at least
+// SAMPLE_TIME, after a short warm up.
+const SAMPLES: usize = 10;
+const SAMPLE_TIME: Duration = Duration::from_millis(100);
+const WARM_UP_TIME: Duration = Duration::from_millis(200);
+
+// A change against the baseline only counts when it's this many standard
+// deviations of the samples away, and this big a fraction of the time.
+const NOISE_DEVIATIONS: f64 = 3.0;
+const NOISE_FRACTION: f64 = 0.02;
+
+struct Bench {
+    filter: Option<String>,
+    baseline: HashMap<String, f64>,
+    save: Option<String>,
+    // Mean nanoseconds per iteration of every benchmark run so far.
+    results: Vec<(String, f64)>,
+}
+
+impl Bench {
+    fn from_args() -> Self {
+        let mut bench = Bench {
+            filter: None,
+            baseline: HashMap::new(),
+            save: None,
+            results: Vec::new(),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--baseline" => {
+                    let path = args.next().expect("--baseline needs a file");
+                    let text =
+                        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+                    bench.baseline = text
+                        .lines()
+                        .filter_map(|line| {
+                            let (name, nanos) = line.split_once(' ')?;
+                            Some((name.to_string(), nanos.parse().ok()?))
+                        })
+                        .collect();
+                }
+                "--save-baseline" => {
+                    bench.save = Some(args.next().expect("--save-baseline needs a file"));
+                }
+                // Flags cargo passes along, like --bench.
+                _ if arg.starts_with("--") => {}
+                _ => bench.filter = Some(arg),
+            }
+        }
+        bench
+    }
+
+    fn run(&mut self, name: &str, mut f: impl FnMut()) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter.as_str()))
+        {
+            return;
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < WARM_UP_TIME {
+            f();
+        }
+
+        let mut iterations = 0u64;
+        let samples: Vec<f64> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                let mut count = 0u64;
+                while start.elapsed() < SAMPLE_TIME {
+                    f();
+                    count += 1;
+                }
+                iterations += count;
+                start.elapsed().as_nanos() as f64 / count as f64
+            })
+            .collect();
+
+        let mean = samples.iter().sum::<f64>() / SAMPLES as f64;
+        let deviation =
+            (samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (SAMPLES - 1) as f64).sqrt();
+
+        let change = match self.baseline.get(name) {
+            Some(&before) => {
+                let difference = mean - before;
+                let verdict = if difference.abs() < NOISE_DEVIATIONS * deviation
+                    || difference.abs() < NOISE_FRACTION * before
+                {
+                    "no change"
+                } else if difference > 0.0 {
+                    "REGRESSED"
+                } else {
+                    "improved"
+                };
+                format!("   {:+.1}% {verdict}", difference / before * 100.0)
+            }
+            None => String::new(),
+        };
+
+        println!(
+            "{name:<24} {:>12} ± {:<10}   ({iterations} iterations){change}",
+            format_time(mean),
+            format_time(deviation),
+        );
+        self.results.push((name.to_string(), mean));
+    }
+
+    fn finish(self) {
+        let Some(path) = self.save else {
+            return;
+        };
+        let text: String = self
+            .results
+            .iter()
+            .map(|(name, nanos)| format!("{name} {nanos}\n"))
+            .collect();
+        std::fs::write(&path, text).unwrap_or_else(|e| panic!("{path}: {e}"));
+        println!("saved baseline to {path}");
+    }
+}
+
+fn format_time(nanos: f64) -> String {
+    if nanos >= 1e6 {
+        format!("{:.2} ms", nanos / 1e6)
+    } else if nanos >= 1e3 {
+        format!("{:.2} µs", nanos / 1e3)
+    } else {
+        format!("{nanos:.1} ns")
+    }
+}
+
+fn main() {
+    let mut bench = Bench::from_args();
+
+    // A ray from the origin straight down +z hits every primitive below.
+    let origin = Vec3::ZERO;
+    let direction = Vec3::Z;
+
+    let sphere = Sphere {
+        center: Vec3::new(0.0, 0.0, 4.0),
+        radius: 1.0,
+        texture: None,
+        emission: 0.0,
+    };
+    let triangle = Triangle {
+        vertex1: Vec3::new(-1.0, -1.0, 4.0),
+        vertex2: Vec3::new(0.0, 1.0, 4.0),
+        vertex3: Vec3::new(1.0, -1.0, 4.0),
+        normals: None,
+        texture: None,
+        emission: 0.0,
+    };
+    let cuboid = Cuboid {
+        center: Vec3::new(0.0, 0.0, 4.0),
+        half_extents: Vec3::splat(0.5),
+        rotation: Mat3::from_rotation_y(0.5),
+        texture: None,
+        emission: 0.0,
+    };
+    let torus = Torus {
+        center: Vec3::new(0.0, 0.0, 4.0),
+        major_radius: 0.6,
+        minor_radius: 0.2,
+        rotation: Mat3::from_rotation_x(1.2),
+        emission: 0.0,
+    };
+
+    bench.run("sphere", || {
+        black_box(ray_intersects_sphere(
+            black_box(origin),
+            black_box(direction),
+            &sphere,
+        ));
+    });
+    bench.run("triangle", || {
+        black_box(ray_intersects_triangle(
+            black_box(origin),
+            black_box(direction),
+            &triangle,
+        ));
+    });
+    bench.run("cuboid", || {
+        black_box(ray_intersects_cuboid(
+            black_box(origin),
+            black_box(direction),
+            &cuboid,
+        ));
+    });
+    bench.run("torus", || {
+        black_box(ray_intersects_torus(
+            black_box(origin),
+            black_box(direction),
+            &torus,
+        ));
+    });
+
+    // The same rays against a dozen spheres, one at a time and as a packet.
+    let spheres: Vec<Object> = (0..12)
+        .map(|i| {
+            Object::Sphere(Sphere {
+                center: Vec3::new(i as f32 - 6.0, 0.0, 8.0),
+                radius: 0.5,
+                texture: None,
+                emission: 0.0,
+            })
+        })
+        .collect();
+    let rays: [(Vec3, Vec3); LANES] =
+        std::array::from_fn(|l| (Vec3::ZERO, Vec3::new(l as f32 * 0.1 - 0.2, 0.0, 1.0)));
+
+    bench.run("spheres_scalar", || {
+        for &(origin, direction) in &rays {
+            black_box(closest_hit(
+                black_box(origin),
+                black_box(direction),
+                1.0,
+                f32::INFINITY,
+                &spheres,
+            ));
+        }
+    });
+    let packet = RayPacket::new(&rays);
+    bench.run("spheres_packet", || {
+        black_box(closest_hits(
+            black_box(&packet),
+            1.0,
+            f32::INFINITY,
+            &spheres,
+        ));
+    });
+
+    let scene = Scene {
+        objects: default_scene(),
+        meshes: Vec::new(),
+        animations: Vec::new(),
+        bodies: Vec::new(),
+        background: None,
+        bookmarks: Vec::new(),
+        cameras: Vec::new(),
+    };
+    let settings = Settings::default();
+    let camera = Camera::new(&settings);
+    let (origin, direction) = camera.generate_ray(0.0, 0.0);
+
+    bench.run("closest_hit", || {
+        black_box(closest_hit(
+            black_box(origin),
+            black_box(direction),
+            1.0,
+            f32::INFINITY,
+            &scene.objects,
+        ));
+    });
+    bench.run("trace_view", || {
+        black_box(trace_view(
+            black_box(origin),
+            black_box(direction),
+            &scene.objects,
+            None,
+            &settings,
+        ));
+    });
+
+    // Whole frames are traced from scratch every time, as if the camera
+    // moved on every frame.
+    let mut renderer = Renderer::new(Settings::default());
+    bench.run("frame", || {
+        renderer.cache.invalidate();
+        black_box(renderer.render_frame(&scene, &camera));
+    });
+
+    renderer.settings.samples = 4;
+    bench.run("frame_4_samples", || {
+        renderer.cache.invalidate();
+        black_box(renderer.render_frame(&scene, &camera));
+    });
+
+    renderer.settings.samples = 1;
+    renderer.settings.mode = RenderMode::Braille;
+    bench.run("frame_braille", || {
+        renderer.cache.invalidate();
+        black_box(renderer.render_frame(&scene, &camera));
+    });
+
+    // Thousands of objects, where the cost of testing every object for every
+    // ray dominates.
+    let crowd = Scene {
+        objects: generate(Generator::Spheres, 1, 10_000),
+        meshes: Vec::new(),
+        animations: Vec::new(),
+        bodies: Vec::new(),
+        background: None,
+        bookmarks: Vec::new(),
+        cameras: Vec::new(),
+    };
+    renderer.settings.mode = RenderMode::Ramp;
+    bench.run("frame_10k_spheres", || {
+        renderer.cache.invalidate();
+        black_box(renderer.render_frame(&crowd, &camera));
+    });
+
+    let image_settings = Settings {
+        image_width: 480,
+        image_height: 270,
+        image_samples: 1,
+        ..Settings::default()
+    };
+    bench.run("image_480x270", || {
+        black_box(render_image(&scene, &camera, &image_settings));
+    });
+
+    bench.finish();
+}
@@ -0,0 +1,105 @@
+use crate::render::{FrameBuffer, COLS, ROWS};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// How character frames are written out for playing back in a terminal.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AsciiFormat {
+    // An asciinema v2 recording: a header line, then one output event per
+    // frame that moves the cursor home and redraws the whole grid.
+    Cast,
+    // Every frame one after another in a plain text file, each under a line
+    // giving its number and time.
+    Text,
+}
+
+pub const ASCII_FORMATS: [AsciiFormat; 2] = [AsciiFormat::Cast, AsciiFormat::Text];
+
+impl AsciiFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        ASCII_FORMATS.into_iter().find(|f| f.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AsciiFormat::Cast => "cast",
+            AsciiFormat::Text => "text",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Header {
+    version: u32,
+    width: usize,
+    height: usize,
+    title: &'static str,
+}
+
+pub struct AsciiWriter {
+    writer: BufWriter<File>,
+    format: AsciiFormat,
+    frames: usize,
+}
+
+impl AsciiWriter {
+    pub fn create(path: &str, format: AsciiFormat) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+        let mut writer = BufWriter::new(file);
+
+        if format == AsciiFormat::Cast {
+            let header = Header {
+                version: 2,
+                width: COLS,
+                height: ROWS,
+                title: "cast",
+            };
+            let line = serde_json::to_string(&header).map_err(|e| e.to_string())?;
+            writeln!(writer, "{line}").map_err(|e| e.to_string())?;
+        }
+
+        Ok(AsciiWriter {
+            writer,
+            format,
+            frames: 0,
+        })
+    }
+
+    // Writes the frame as shown `time` seconds into the recording.
+    pub fn write_frame(&mut self, time: f32, frame: &FrameBuffer) -> Result<(), String> {
+        // The buffer is bottom row first, terminals go top down.
+        let rows = frame
+            .cells
+            .chunks(COLS)
+            .rev()
+            .map(|row| row.iter().collect::<String>());
+
+        match self.format {
+            AsciiFormat::Cast => {
+                // The first frame also clears whatever was on screen before.
+                let home = if self.frames == 0 {
+                    "\x1b[2J\x1b[H"
+                } else {
+                    "\x1b[H"
+                };
+                let screen = format!("{home}{}", rows.collect::<Vec<_>>().join("\r\n"));
+                let event =
+                    serde_json::to_string(&(time, "o", screen)).map_err(|e| e.to_string())?;
+                writeln!(self.writer, "{event}").map_err(|e| e.to_string())?;
+            }
+            AsciiFormat::Text => {
+                writeln!(self.writer, "frame {} at {time:.3}s", self.frames)
+                    .map_err(|e| e.to_string())?;
+                for row in rows {
+                    writeln!(self.writer, "{row}").map_err(|e| e.to_string())?;
+                }
+            }
+        }
+
+        self.frames += 1;
+        // Flushed every frame so the file can be played while it's still
+        // being written.
+        self.writer.flush().map_err(|e| e.to_string())
+    }
+}
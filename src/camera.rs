@@ -0,0 +1,278 @@
+use crate::render::{CELL_HEIGHT, CELL_WIDTH, COLS, ROWS};
+use crate::settings::Settings;
+use notan::math::{Mat3, Vec3};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+
+// The constant 'D' represents the distance between the camera and the projection plane.
+pub const D: f32 = 1.0;
+
+#[derive(Deserialize)]
+pub struct Keyframe {
+    pub time: f32,
+    pub position: Vec3,
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default)]
+    pub pitch: f32,
+}
+
+#[derive(Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn duration(&self) -> (f32, f32) {
+        let first = self.keyframes.first().map_or(0.0, |k| k.time);
+        let last = self.keyframes.last().map_or(0.0, |k| k.time);
+        (first, last)
+    }
+
+    // Linearly interpolates position and yaw/pitch (in degrees) between the
+    // keyframes either side of `time`, holding the ends of the path.
+    pub fn sample(&self, time: f32) -> (Vec3, Mat3) {
+        let next = self
+            .keyframes
+            .iter()
+            .position(|k| k.time > time)
+            .unwrap_or(self.keyframes.len() - 1);
+        let prev = next.saturating_sub(1);
+
+        let (a, b) = (&self.keyframes[prev], &self.keyframes[next]);
+        let span = b.time - a.time;
+        let f = if span > 0.0 {
+            ((time - a.time) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let position = a.position.lerp(b.position, f);
+        let yaw = (a.yaw + (b.yaw - a.yaw) * f).to_radians();
+        let pitch = (a.pitch + (b.pitch - a.pitch) * f).to_radians();
+
+        (
+            position,
+            Mat3::from_rotation_y(yaw) * Mat3::from_rotation_x(pitch),
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct Viewport {
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    // Parallel rays from across a plane ORTHOGRAPHIC_SCALE times the size of
+    // the viewport.
+    Orthographic,
+    // Equidistant fisheye: the angle off-axis grows linearly with the
+    // distance from the center, reaching FISHEYE_FOV / 2 at the sides.
+    Fisheye,
+}
+
+pub const PROJECTIONS: [Projection; 3] = [
+    Projection::Perspective,
+    Projection::Orthographic,
+    Projection::Fisheye,
+];
+
+pub const ORTHOGRAPHIC_SCALE: f32 = 8.0;
+pub const FISHEYE_FOV: f32 = 180.0;
+
+impl Projection {
+    pub fn parse(name: &str) -> Option<Self> {
+        PROJECTIONS.into_iter().find(|p| p.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic => "orthographic",
+            Projection::Fisheye => "fisheye",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let i = PROJECTIONS.iter().position(|p| *p == self).unwrap();
+        PROJECTIONS[(i + 1) % PROJECTIONS.len()]
+    }
+}
+
+#[derive(Clone)]
+pub struct Camera {
+    pub position: Vec3,
+    pub rotation: Mat3,
+    pub viewport: Viewport,
+    pub projection: Projection,
+    // Thin lens for depth of field: rays start across a disc of this radius
+    // and converge at `focus_distance`. An aperture of zero is a pinhole.
+    pub aperture: f32,
+    pub focus_distance: f32,
+    // Width over height of the viewport, which should match the shape of
+    // whatever it is drawn to for circles to come out round.
+    pub aspect: f32,
+}
+
+// The horizontal field of view of the original 1x1 viewport at D.
+pub const DEFAULT_FOV: f32 = 53.130_1;
+
+// The shape of the character grid on screen: cells are twice as tall as they
+// are wide, so rows cover far more of the window than columns do.
+pub const GRID_ASPECT: f32 = (COLS * CELL_WIDTH) as f32 / (ROWS * CELL_HEIGHT) as f32;
+
+impl Camera {
+    pub fn new(settings: &Settings) -> Self {
+        let mut camera = Camera {
+            position: Vec3::default(),
+            rotation: Mat3::default(),
+            viewport: Viewport {
+                width: 1.0,
+                height: 1.0,
+            },
+            projection: settings.projection,
+            aperture: settings.aperture,
+            focus_distance: settings.focus_distance,
+            aspect: settings.aspect.unwrap_or(GRID_ASPECT),
+        };
+        camera.set_fov(settings.fov);
+        camera
+    }
+
+    pub fn fov(&self) -> f32 {
+        2.0 * (self.viewport.width / (2.0 * D)).atan().to_degrees()
+    }
+
+    // Sizes the viewport for a horizontal field of view in degrees, keeping
+    // its aspect.
+    pub fn set_fov(&mut self, degrees: f32) {
+        let width = 2.0 * D * (degrees.clamp(1.0, 170.0).to_radians() / 2.0).tan();
+        self.viewport = Viewport {
+            width,
+            height: width / self.aspect,
+        };
+    }
+
+    // Reshapes the viewport, keeping the horizontal field of view.
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+        self.viewport.height = self.viewport.width / aspect;
+    }
+
+    // Turns a camera space direction through the viewport into a world space
+    // ray starting on the lens at `lens`, a point in the unit square. The
+    // direction keeps a depth of D so ray distances still count viewport
+    // planes.
+    pub fn lens_ray(&self, local: Vec3, lens: (f32, f32)) -> (Vec3, Vec3) {
+        if self.aperture <= 0.0 {
+            return (self.position, self.rotation * local);
+        }
+
+        let radius = self.aperture * lens.0.sqrt();
+        let angle = std::f32::consts::TAU * lens.1;
+        let offset = Vec3::new(radius * angle.cos(), radius * angle.sin(), 0.0);
+
+        let focus = local * (self.focus_distance / local.z);
+        let direction = (focus - offset) * (D / self.focus_distance);
+
+        (
+            self.position + self.rotation * offset,
+            self.rotation * direction,
+        )
+    }
+
+    // The ray through the center of the cell at (x, y), with cells counted
+    // from the middle of the grid.
+    pub fn generate_ray(&self, x: f32, y: f32) -> (Vec3, Vec3) {
+        self.generate_lens_ray(x, y, (0.0, 0.0))
+    }
+
+    pub fn generate_lens_ray(&self, x: f32, y: f32, lens: (f32, f32)) -> (Vec3, Vec3) {
+        self.canvas_ray(x, y, COLS as f32, ROWS as f32, lens)
+    }
+
+    // Ray through (x, y) on a canvas of any size, measured from its center
+    // with y up. Only the perspective projection has a lens.
+    pub fn canvas_ray(
+        &self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        lens: (f32, f32),
+    ) -> (Vec3, Vec3) {
+        let local = self.canvas_to_viewport_distance(x, y, width, height);
+
+        match self.projection {
+            Projection::Perspective => self.lens_ray(local, lens),
+            Projection::Orthographic => {
+                let offset = Vec3::new(local.x, local.y, 0.0) * ORTHOGRAPHIC_SCALE;
+                (self.position + self.rotation * offset, self.forward())
+            }
+            Projection::Fisheye => {
+                let radius = local.x.hypot(local.y);
+                let theta = radius / (self.viewport.width / 2.0) * (FISHEYE_FOV / 2.0).to_radians();
+                let phi = local.y.atan2(local.x);
+
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ) * D;
+                (self.position, self.rotation * direction)
+            }
+        }
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * Vec3::from_array([0.0, 0.0, D])
+    }
+
+    // Inverse of camera_pixel_to_viewport_distance: the cell a world point
+    // lands in and its distance along that cell's ray, or None if the point
+    // is behind the viewport.
+    pub fn project(&self, point: Vec3) -> Option<(f32, f32, f32)> {
+        let local = self.rotation.transpose() * (point - self.position);
+        if local.z < D {
+            return None;
+        }
+
+        let t = local.z / D;
+        Some((
+            local.x / t * COLS as f32 / self.viewport.width,
+            local.y / t * ROWS as f32 / self.viewport.height,
+            t,
+        ))
+    }
+
+    pub fn camera_pixel_to_viewport_distance(&self, x: f32, y: f32) -> Vec3 {
+        self.canvas_to_viewport_distance(x, y, COLS as f32, ROWS as f32)
+    }
+
+    // Same mapping as the character grid but for a canvas of any size, so
+    // exported images cover exactly the view shown on screen.
+    pub fn canvas_to_viewport_distance(&self, x: f32, y: f32, width: f32, height: f32) -> Vec3 {
+        Vec3 {
+            x: x * self.viewport.width / width,
+            y: y * self.viewport.height / height,
+            z: D,
+        }
+    }
+}
+
+pub fn load_camera_path(path: &str) -> Result<CameraPath, String> {
+    let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    let camera_path: CameraPath =
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| format!("{path}: {e}"))?;
+
+    if camera_path.keyframes.is_empty() {
+        return Err(format!("{path}: camera path has no keyframes"));
+    }
+
+    Ok(camera_path)
+}
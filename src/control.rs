@@ -0,0 +1,769 @@
+use crate::camera::Camera;
+use crate::geometry::closest_hit;
+use crate::render::{COLS, ROWS};
+use crate::scene::{Body, Bookmark, Capsule, Cuboid, Cylinder, Object, Scene, Sphere, Torus};
+use crate::settings::{Movement, Settings, RESOLUTIONS};
+use notan::math::{Mat3, Vec3};
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Everything a held key can ask for. The front end decides which keys these
+// are and passes in the ones down this frame.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Forward,
+    Back,
+    StrafeLeft,
+    StrafeRight,
+    TurnLeft,
+    TurnRight,
+    ZoomIn,
+    ZoomOut,
+    FocusNearer,
+    FocusFurther,
+    NudgeForward,
+    NudgeBack,
+    NudgeLeft,
+    NudgeRight,
+    NudgeUp,
+    NudgeDown,
+    Grow,
+    Shrink,
+    RotateLeft,
+    RotateRight,
+}
+
+pub fn move_camera(camera: &mut Camera, held: &[Action], settings: &Settings) {
+    let (step, turn) = (settings.move_speed, settings.look_speed);
+
+    for action in held {
+        match action {
+            Action::Forward => camera.position += camera.rotation * Vec3::new(0.0, 0.0, step),
+            Action::Back => camera.position -= camera.rotation * Vec3::new(0.0, 0.0, step),
+            Action::StrafeLeft => camera.position -= camera.rotation * Vec3::new(step, 0.0, 0.0),
+            Action::StrafeRight => camera.position += camera.rotation * Vec3::new(step, 0.0, 0.0),
+            Action::TurnRight => camera.rotation *= Mat3::from_rotation_y(turn),
+            Action::TurnLeft => camera.rotation *= Mat3::from_rotation_y(turn).inverse(),
+            _ => {}
+        }
+    }
+}
+
+// How quickly the lens changes while a key is held, in degrees of field of
+// view and units of focus distance a frame.
+const FOV_SPEED: f32 = 0.5;
+const FOCUS_SPEED: f32 = 0.05;
+
+// Zooms by narrowing or widening the field of view and pulls the focus nearer
+// or further, returning whether anything changed.
+pub fn adjust_lens(camera: &mut Camera, held: &[Action]) -> bool {
+    let (fov, focus) = (camera.fov(), camera.focus_distance);
+
+    for action in held {
+        match action {
+            Action::ZoomIn => camera.set_fov(fov - FOV_SPEED),
+            Action::ZoomOut => camera.set_fov(fov + FOV_SPEED),
+            Action::FocusNearer => camera.focus_distance = (focus - FOCUS_SPEED).max(FOCUS_SPEED),
+            Action::FocusFurther => camera.focus_distance = focus + FOCUS_SPEED,
+            _ => {}
+        }
+    }
+
+    camera.fov() != fov || camera.focus_distance != focus
+}
+
+// Camera collision: how near the camera comes to a surface, and when walking
+// how high above the ground it's held, the tallest step it climbs and how far
+// down it looks for ground before leaving it hanging where it is.
+const CAMERA_RADIUS: f32 = 0.2;
+const EYE_HEIGHT: f32 = 1.0;
+const STEP_HEIGHT: f32 = 0.3;
+const GROUND_DISTANCE: f32 = 10.0;
+
+// Where the camera ends up moving `motion` from `from` in the given mode.
+pub fn constrain_movement(
+    from: Vec3,
+    motion: Vec3,
+    objects: &[Object],
+    movement: Movement,
+) -> Vec3 {
+    match movement {
+        Movement::Noclip => from + motion,
+        Movement::Fly => slide(from, motion, objects),
+        Movement::Walk => {
+            let to = slide(from, Vec3::new(motion.x, 0.0, motion.z), objects);
+            let ground = ground_below(to, objects);
+            match (ground_below(from, objects), ground) {
+                (Some(before), Some(after)) if after > before + STEP_HEIGHT => from,
+                (_, Some(after)) => Vec3::new(to.x, after + EYE_HEIGHT, to.z),
+                (_, None) => to,
+            }
+        }
+    }
+}
+
+// Moves as far as it can towards each surface in the way, then carries on
+// along it with what's left of the motion, so that walls are slid along rather
+// than stuck to. Surfaces are only solid from the outside, so a camera that
+// starts inside an object can still get out.
+fn slide(mut position: Vec3, mut motion: Vec3, objects: &[Object]) -> Vec3 {
+    // Enough for the inside corner of two walls and a floor.
+    for _ in 0..3 {
+        let distance = motion.length();
+        if distance < 1e-6 {
+            break;
+        }
+        let direction = motion / distance;
+
+        let hit = closest_hit(position, direction, 0.0, distance + CAMERA_RADIUS, objects)
+            .map(|(_, hit)| hit)
+            .filter(|hit| hit.normal.dot(direction) < 0.0);
+        let Some(hit) = hit else {
+            return position + motion;
+        };
+
+        let travel = (hit.t - CAMERA_RADIUS).max(0.0);
+        position += direction * travel;
+        let normal = hit.normal.normalize();
+        let remaining = direction * (distance - travel);
+        motion = remaining - normal * remaining.dot(normal);
+    }
+    position
+}
+
+// The height of the first surface straight below `position`.
+fn ground_below(position: Vec3, objects: &[Object]) -> Option<f32> {
+    closest_hit(position, -Vec3::Y, 0.0, GROUND_DISTANCE, objects)
+        .map(|(_, hit)| position.y - hit.t)
+}
+
+// Gamepads are read straight from the Linux joystick interface. notan has no
+// gamepad input of its own, and gilrs isn't among the crates this builds
+// from. Elsewhere, including Windows, macOS and the web, or with nothing
+// plugged in, there is simply no gamepad.
+pub const GAMEPAD_DEVICE: &str = "/dev/input/js0";
+
+// Axis numbers for an Xbox style pad under the xpad driver. Triggers rest at
+// -1 and read +1 fully pressed.
+const AXIS_LEFT_X: usize = 0;
+const AXIS_LEFT_Y: usize = 1;
+const AXIS_LEFT_TRIGGER: usize = 2;
+const AXIS_RIGHT_X: usize = 3;
+const AXIS_RIGHT_Y: usize = 4;
+const AXIS_RIGHT_TRIGGER: usize = 5;
+
+const RESTING_AXES: [f32; 8] = [0.0, 0.0, -1.0, 0.0, 0.0, -1.0, 0.0, 0.0];
+
+pub struct Gamepad {
+    axes: Arc<Mutex<[f32; 8]>>,
+}
+
+impl Gamepad {
+    // Starts a thread that keeps the axes up to date. If the pad is unplugged
+    // the thread stops and every axis returns to rest.
+    pub fn open() -> Option<Self> {
+        let mut device = File::open(GAMEPAD_DEVICE).ok()?;
+        let axes = Arc::new(Mutex::new(RESTING_AXES));
+
+        let shared = Arc::clone(&axes);
+        std::thread::spawn(move || {
+            // Each event is a u32 timestamp, an i16 value, a type and a number.
+            const AXIS_EVENT: u8 = 0x02;
+            let mut event = [0u8; 8];
+
+            while device.read_exact(&mut event).is_ok() {
+                let value = i16::from_le_bytes([event[4], event[5]]);
+                let (kind, number) = (event[6] & !0x80, event[7] as usize);
+
+                if kind == AXIS_EVENT && number < 8 {
+                    shared.lock().unwrap()[number] = value as f32 / i16::MAX as f32;
+                }
+            }
+
+            *shared.lock().unwrap() = RESTING_AXES;
+        });
+
+        Some(Gamepad { axes })
+    }
+
+    // A pad held at recorded positions, for replays.
+    pub fn fixed(axes: [f32; 8]) -> Self {
+        Gamepad {
+            axes: Arc::new(Mutex::new(axes)),
+        }
+    }
+
+    pub fn axes(&self) -> [f32; 8] {
+        *self.axes.lock().unwrap()
+    }
+}
+
+// A stick's position with the deadzone cut out of the middle and the rest
+// rescaled, so movement starts smoothly from zero at its edge.
+fn stick(x: f32, y: f32, deadzone: f32) -> (f32, f32) {
+    let magnitude = x.hypot(y);
+    if magnitude <= deadzone {
+        return (0.0, 0.0);
+    }
+
+    let scale = ((magnitude - deadzone) / (1.0 - deadzone)).min(1.0) / magnitude;
+    (x * scale, y * scale)
+}
+
+fn trigger(value: f32) -> f32 {
+    (value + 1.0) / 2.0
+}
+
+// Left stick moves and strafes, right stick turns and looks up and down, and
+// the right and left triggers rise and sink. Stick y axes read negative when
+// pushed forward. At full deflection and a sensitivity of one they move and
+// turn as fast as the keyboard.
+pub fn apply_gamepad(axes: [f32; 8], camera: &mut Camera, settings: &Settings) {
+    let speed = settings.gamepad_sensitivity;
+    let (step, turn) = (settings.move_speed * speed, settings.look_speed * speed);
+    let (move_x, move_y) = stick(axes[AXIS_LEFT_X], axes[AXIS_LEFT_Y], settings.deadzone);
+    let (look_x, look_y) = stick(axes[AXIS_RIGHT_X], axes[AXIS_RIGHT_Y], settings.deadzone);
+    let rise = trigger(axes[AXIS_RIGHT_TRIGGER]) - trigger(axes[AXIS_LEFT_TRIGGER]);
+
+    camera.position += camera.rotation * Vec3::new(move_x, 0.0, -move_y) * step;
+    camera.position.y += rise * step;
+
+    // Yaw about the world's vertical so looking up and down never rolls.
+    camera.rotation = Mat3::from_rotation_y(look_x * turn)
+        * camera.rotation
+        * Mat3::from_rotation_x(look_y * turn);
+}
+
+// Keeps where the camera is in a bookmark slot, replacing what was there.
+pub fn save_bookmark(scene: &mut Scene, slot: usize, camera: &Camera) {
+    let bookmark = Bookmark {
+        slot,
+        position: camera.position,
+        rotation: camera.rotation,
+    };
+    match scene.bookmarks.iter_mut().find(|b| b.slot == slot) {
+        Some(existing) => *existing = bookmark,
+        None => scene.bookmarks.push(bookmark),
+    }
+}
+
+// Moves the camera to a bookmark, returning whether the slot had one.
+pub fn recall_bookmark(scene: &Scene, slot: usize, camera: &mut Camera) -> bool {
+    let Some(bookmark) = scene.bookmarks.iter().find(|b| b.slot == slot) else {
+        return false;
+    };
+    camera.position = bookmark.position;
+    camera.rotation = bookmark.rotation;
+    true
+}
+
+// The shapes the editor can add.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    Sphere,
+    Cuboid,
+    Cylinder,
+    Capsule,
+    Torus,
+}
+
+// How far in front of the camera new objects appear.
+const SPAWN_DISTANCE: f32 = 3.0;
+
+fn spawn_point(camera: &Camera) -> Vec3 {
+    camera.position + camera.forward() * SPAWN_DISTANCE
+}
+
+// A new object of the given shape just in front of the camera.
+pub fn spawn(shape: Shape, camera: &Camera) -> Object {
+    let center = spawn_point(camera);
+    let up = Vec3::new(0.0, 0.5, 0.0);
+
+    match shape {
+        Shape::Sphere => Object::Sphere(Sphere {
+            center,
+            radius: 0.5,
+            texture: None,
+            emission: 0.0,
+        }),
+        Shape::Cuboid => Object::Cuboid(Cuboid {
+            center,
+            half_extents: Vec3::new(0.5, 0.5, 0.5),
+            rotation: Mat3::default(),
+            texture: None,
+            emission: 0.0,
+        }),
+        Shape::Cylinder => Object::Cylinder(Cylinder {
+            start: center - up,
+            end: center + up,
+            radius: 0.5,
+            emission: 0.0,
+        }),
+        Shape::Capsule => Object::Capsule(Capsule {
+            start: center - up,
+            end: center + up,
+            radius: 0.4,
+            emission: 0.0,
+        }),
+        Shape::Torus => Object::Torus(Torus {
+            center,
+            major_radius: 0.6,
+            minor_radius: 0.2,
+            rotation: Mat3::default(),
+            emission: 0.0,
+        }),
+    }
+}
+
+// Adds a ball in front of the camera that falls and bounces, returning its
+// index.
+pub fn drop_ball(scene: &mut Scene, camera: &Camera) -> usize {
+    scene.objects.push(Object::Sphere(Sphere {
+        center: spawn_point(camera),
+        radius: 0.3,
+        texture: None,
+        emission: 0.0,
+    }));
+    let object = scene.objects.len() - 1;
+    scene.bodies.push(Body {
+        object,
+        velocity: Vec3::default(),
+    });
+    object
+}
+
+// How quickly the selected object is moved, resized or turned a frame while a
+// key is held.
+const NUDGE_SPEED: f32 = 0.05;
+const SCALE_SPEED: f32 = 1.02;
+const ROTATE_SPEED: f32 = 0.025;
+
+// A change to the selected object from the keys held this frame. Nudges are
+// relative to the way the camera faces, except up and down.
+pub struct Edit {
+    offset: Vec3,
+    scale: f32,
+    turn: f32,
+}
+
+impl Edit {
+    pub fn from_held(camera: &Camera, held: &[Action]) -> Option<Self> {
+        let mut edit = Edit {
+            offset: Vec3::default(),
+            scale: 1.0,
+            turn: 0.0,
+        };
+        let (forward, right) = (
+            camera.rotation * Vec3::new(0.0, 0.0, NUDGE_SPEED),
+            camera.rotation * Vec3::new(NUDGE_SPEED, 0.0, 0.0),
+        );
+
+        let mut edited = false;
+        for action in held {
+            match action {
+                Action::NudgeForward => edit.offset += forward,
+                Action::NudgeBack => edit.offset -= forward,
+                Action::NudgeLeft => edit.offset -= right,
+                Action::NudgeRight => edit.offset += right,
+                Action::NudgeUp => edit.offset.y += NUDGE_SPEED,
+                Action::NudgeDown => edit.offset.y -= NUDGE_SPEED,
+                Action::Grow => edit.scale *= SCALE_SPEED,
+                Action::Shrink => edit.scale /= SCALE_SPEED,
+                Action::RotateLeft => edit.turn -= ROTATE_SPEED,
+                Action::RotateRight => edit.turn += ROTATE_SPEED,
+                _ => continue,
+            }
+            edited = true;
+        }
+
+        edited.then_some(edit)
+    }
+
+    pub fn apply(&self, object: &mut Object) {
+        object.translate(self.offset);
+        if self.scale != 1.0 {
+            object.scale(self.scale);
+        }
+        if self.turn != 0.0 {
+            object.rotate(Mat3::from_rotation_y(self.turn));
+        }
+    }
+}
+
+// Dynamic resolution: how quickly the average trace cost follows new frames,
+// and the share of the frame time a finer resolution must be expected to fit
+// in before switching up, which keeps it from flickering between two.
+const COST_SMOOTHING: f32 = 0.25;
+const HEADROOM: f32 = 0.7;
+
+// Halves the resolution when frames take longer to trace than the target
+// frame rate allows, and doubles it again once there's room, returning
+// whether it changed. `cost` carries the average trace time of a whole grid
+// from one frame to the next. Frames that only retraced `cells` of the grid
+// are scaled up to what the whole grid would have cost. Each step changes the
+// rays traced fourfold.
+pub fn adjust_resolution(
+    settings: &mut Settings,
+    cost: &mut Option<f32>,
+    cells: usize,
+    trace_time: Duration,
+) -> bool {
+    if settings.target_fps <= 0.0 || settings.path_traced() || cells == 0 {
+        return false;
+    }
+
+    let resolution = settings.resolution;
+    let blocks = COLS.div_ceil(resolution) * ROWS.div_ceil(resolution);
+    let frame_cost = trace_time.as_secs_f32() * blocks as f32 / cells as f32;
+    let average = match *cost {
+        Some(average) => average + (frame_cost - average) * COST_SMOOTHING,
+        None => frame_cost,
+    };
+
+    let budget = settings.target_fps.recip();
+    let level = RESOLUTIONS
+        .iter()
+        .position(|r| *r == resolution)
+        .unwrap_or(0);
+    let level = if average > budget && level + 1 < RESOLUTIONS.len() {
+        level + 1
+    } else if average * 4.0 < budget * HEADROOM && level > 0 {
+        level - 1
+    } else {
+        *cost = Some(average);
+        return false;
+    };
+
+    settings.resolution = RESOLUTIONS[level];
+    let scale = settings.resolution as f32 / resolution as f32;
+    *cost = Some(average / (scale * scale));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Plane;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.distance(b) < 1e-3, "{a:?} is not near {b:?}");
+    }
+
+    // A camera at the origin looking down +Z.
+    fn camera() -> Camera {
+        Camera::new(&Settings::default())
+    }
+
+    fn floor(y: f32) -> Object {
+        Object::Plane(Plane {
+            point: Vec3::new(0.0, y, 0.0),
+            normal: Vec3::Y,
+            checker: None,
+            emission: 0.0,
+        })
+    }
+
+    // A slab whose near face is at `z`, a metre thick.
+    fn wall(z: f32) -> Object {
+        Object::Cuboid(Cuboid {
+            center: Vec3::new(0.0, 0.0, z + 0.5),
+            half_extents: Vec3::new(5.0, 5.0, 0.5),
+            rotation: Mat3::default(),
+            texture: None,
+            emission: 0.0,
+        })
+    }
+
+    fn step(top: f32) -> Object {
+        Object::Cuboid(Cuboid {
+            center: Vec3::new(0.0, top - 0.5, 3.0),
+            half_extents: Vec3::new(5.0, 0.5, 1.0),
+            rotation: Mat3::default(),
+            texture: None,
+            emission: 0.0,
+        })
+    }
+
+    #[test]
+    fn held_keys_move_and_turn_the_camera() {
+        let settings = Settings::default();
+        let mut camera = camera();
+
+        move_camera(
+            &mut camera,
+            &[Action::Forward, Action::StrafeRight],
+            &settings,
+        );
+        let step = settings.move_speed;
+        assert_near(camera.position, Vec3::new(step, 0.0, step));
+
+        move_camera(&mut camera, &[Action::Forward, Action::Back], &settings);
+        assert_near(camera.position, Vec3::new(step, 0.0, step));
+
+        move_camera(&mut camera, &[Action::TurnRight], &settings);
+        let turned = Mat3::from_rotation_y(settings.look_speed) * Vec3::Z;
+        assert_near(camera.forward(), turned);
+    }
+
+    #[test]
+    fn the_lens_only_reports_changes() {
+        let mut camera = camera();
+        let fov = camera.fov();
+
+        assert!(!adjust_lens(&mut camera, &[Action::Forward]));
+        assert!(adjust_lens(&mut camera, &[Action::ZoomIn]));
+        assert!((camera.fov() - (fov - FOV_SPEED)).abs() < 1e-4);
+
+        camera.focus_distance = FOCUS_SPEED;
+        assert!(!adjust_lens(&mut camera, &[Action::FocusNearer]));
+        assert_eq!(camera.focus_distance, FOCUS_SPEED);
+    }
+
+    #[test]
+    fn noclip_goes_through_walls() {
+        let objects = [wall(1.0)];
+        let to = constrain_movement(Vec3::ZERO, Vec3::Z * 2.0, &objects, Movement::Noclip);
+        assert_near(to, Vec3::Z * 2.0);
+    }
+
+    #[test]
+    fn flying_stops_short_of_a_wall_and_slides_along_it() {
+        let objects = [wall(1.0)];
+
+        let to = constrain_movement(Vec3::ZERO, Vec3::Z * 2.0, &objects, Movement::Fly);
+        assert_near(to, Vec3::new(0.0, 0.0, 1.0 - CAMERA_RADIUS));
+
+        // At an angle the radius is kept along the way it was moving, so it
+        // stops a little nearer, but still makes all its way across.
+        let motion = Vec3::new(1.0, 0.0, 2.0);
+        let to = constrain_movement(Vec3::ZERO, motion, &objects, Movement::Fly);
+        assert!(to.z > 1.0 - CAMERA_RADIUS && to.z < 1.0, "slid to {to:?}");
+        assert!((to.x - 1.0).abs() < 1e-3, "slid to {to:?}");
+    }
+
+    #[test]
+    fn cameras_inside_an_object_can_get_out() {
+        let objects = [wall(-0.5)];
+        let to = constrain_movement(Vec3::ZERO, -Vec3::Z * 2.0, &objects, Movement::Fly);
+        assert_near(to, -Vec3::Z * 2.0);
+    }
+
+    #[test]
+    fn walking_keeps_to_the_ground() {
+        let objects = [floor(-3.0)];
+        let motion = Vec3::new(0.0, 5.0, 1.0);
+        let to = constrain_movement(Vec3::ZERO, motion, &objects, Movement::Walk);
+        assert_near(to, Vec3::new(0.0, -3.0 + EYE_HEIGHT, 1.0));
+    }
+
+    #[test]
+    fn walking_climbs_low_steps_but_not_high_ones() {
+        let from = Vec3::new(0.0, EYE_HEIGHT, 0.0);
+        let motion = Vec3::Z * 3.0;
+
+        let low = [floor(0.0), step(0.2)];
+        let to = constrain_movement(from, motion, &low, Movement::Walk);
+        assert_near(to, Vec3::new(0.0, 0.2 + EYE_HEIGHT, 3.0));
+
+        let high = [floor(0.0), step(0.5)];
+        let to = constrain_movement(from, motion, &high, Movement::Walk);
+        assert_near(to, from);
+    }
+
+    #[test]
+    fn walking_off_into_nothing_keeps_the_height() {
+        let to = constrain_movement(Vec3::ZERO, Vec3::new(1.0, 1.0, 0.0), &[], Movement::Walk);
+        assert_near(to, Vec3::X);
+    }
+
+    #[test]
+    fn sticks_ignore_the_deadzone_and_rescale_the_rest() {
+        assert_eq!(stick(0.1, 0.1, 0.2), (0.0, 0.0));
+
+        let (x, y) = stick(0.6, 0.0, 0.2);
+        assert!((x - 0.5).abs() < 1e-5 && y == 0.0);
+
+        // Corners reach past one on each axis but keep to the unit circle.
+        let (x, y) = stick(1.0, 1.0, 0.2);
+        assert!((x.hypot(y) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn a_resting_pad_leaves_the_camera_alone() {
+        let mut camera = camera();
+        apply_gamepad(RESTING_AXES, &mut camera, &Settings::default());
+        assert_near(camera.position, Vec3::ZERO);
+        assert_near(camera.forward(), Vec3::Z);
+    }
+
+    #[test]
+    fn full_sticks_and_triggers_move_as_fast_as_the_keyboard() {
+        let settings = Settings::default();
+        let mut camera = camera();
+
+        let mut axes = RESTING_AXES;
+        axes[AXIS_LEFT_Y] = -1.0;
+        axes[AXIS_RIGHT_TRIGGER] = 1.0;
+        apply_gamepad(axes, &mut camera, &settings);
+
+        let step = settings.move_speed * settings.gamepad_sensitivity;
+        assert_near(camera.position, Vec3::new(0.0, step, step));
+    }
+
+    fn empty_scene() -> Scene {
+        Scene {
+            objects: Vec::new(),
+            meshes: Vec::new(),
+            animations: Vec::new(),
+            bodies: Vec::new(),
+            background: None,
+            bookmarks: Vec::new(),
+            cameras: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn bookmarks_are_replaced_and_recalled() {
+        let mut scene = empty_scene();
+        let mut camera = camera();
+
+        assert!(!recall_bookmark(&scene, 1, &mut camera));
+
+        camera.position = Vec3::X;
+        save_bookmark(&mut scene, 1, &camera);
+        camera.position = Vec3::Y;
+        save_bookmark(&mut scene, 1, &camera);
+        assert_eq!(scene.bookmarks.len(), 1);
+
+        camera.position = Vec3::Z;
+        assert!(recall_bookmark(&scene, 1, &mut camera));
+        assert_eq!(camera.position, Vec3::Y);
+    }
+
+    #[test]
+    fn objects_spawn_in_front_of_the_camera() {
+        let mut camera = camera();
+        camera.position = Vec3::new(1.0, 2.0, 3.0);
+
+        let Object::Sphere(sphere) = spawn(Shape::Sphere, &camera) else {
+            panic!("expected a sphere");
+        };
+        assert_near(sphere.center, Vec3::new(1.0, 2.0, 3.0 + SPAWN_DISTANCE));
+    }
+
+    #[test]
+    fn dropped_balls_are_given_a_body() {
+        let mut scene = empty_scene();
+        scene.objects.push(floor(-10.0));
+        let ball = drop_ball(&mut scene, &camera());
+
+        assert_eq!(ball, 1);
+        assert_eq!(scene.bodies.len(), 1);
+        assert_eq!(scene.bodies[0].object, ball);
+    }
+
+    #[test]
+    fn edits_need_a_held_editing_key() {
+        let camera = camera();
+        assert!(Edit::from_held(&camera, &[]).is_none());
+        assert!(Edit::from_held(&camera, &[Action::Forward, Action::ZoomIn]).is_none());
+    }
+
+    #[test]
+    fn edits_nudge_relative_to_the_camera() {
+        let mut camera = camera();
+        camera.rotation = Mat3::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let mut object = spawn(Shape::Sphere, &Camera::new(&Settings::default()));
+
+        let held = [Action::NudgeForward, Action::NudgeUp, Action::Grow];
+        Edit::from_held(&camera, &held).unwrap().apply(&mut object);
+
+        let Object::Sphere(sphere) = object else {
+            unreachable!();
+        };
+        let expected = Vec3::new(NUDGE_SPEED, NUDGE_SPEED, SPAWN_DISTANCE);
+        assert_near(sphere.center, expected);
+        assert!((sphere.radius - 0.5 * SCALE_SPEED).abs() < 1e-5);
+    }
+
+    fn budgeted(target_fps: f32, resolution: usize) -> Settings {
+        Settings {
+            target_fps,
+            resolution,
+            quality: false,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn slow_frames_lower_the_resolution() {
+        let mut settings = budgeted(60.0, 1);
+        let mut cost = None;
+
+        let slow = Duration::from_millis(50);
+        assert!(adjust_resolution(
+            &mut settings,
+            &mut cost,
+            COLS * ROWS,
+            slow
+        ));
+        assert_eq!(settings.resolution, RESOLUTIONS[1]);
+
+        // The cost carried over is what a frame at the new resolution takes.
+        let scale = RESOLUTIONS[1] as f32;
+        assert!((cost.unwrap() - 0.05 / (scale * scale)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fast_frames_raise_the_resolution_only_with_headroom() {
+        let mut settings = budgeted(60.0, RESOLUTIONS[1]);
+        let cells = COLS.div_ceil(RESOLUTIONS[1]) * ROWS.div_ceil(RESOLUTIONS[1]);
+
+        let mut cost = None;
+        let just_fits = Duration::from_secs_f32(1.0 / 60.0 / 4.0);
+        assert!(!adjust_resolution(
+            &mut settings,
+            &mut cost,
+            cells,
+            just_fits
+        ));
+        assert_eq!(settings.resolution, RESOLUTIONS[1]);
+
+        let mut cost = None;
+        let quick = Duration::from_millis(1);
+        assert!(adjust_resolution(&mut settings, &mut cost, cells, quick));
+        assert_eq!(settings.resolution, RESOLUTIONS[0]);
+    }
+
+    #[test]
+    fn partial_frames_count_as_the_whole_grid() {
+        let mut settings = budgeted(60.0, 1);
+        let mut cost = None;
+
+        // A tenth of the grid in a twentieth of the budget fits.
+        let time = Duration::from_secs_f32(1.0 / 1200.0);
+        assert!(!adjust_resolution(
+            &mut settings,
+            &mut cost,
+            COLS * ROWS / 10,
+            time
+        ));
+        assert!((cost.unwrap() - 1.0 / 120.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn resolution_is_left_alone_without_a_target() {
+        let mut settings = budgeted(0.0, 1);
+        let mut cost = None;
+        let slow = Duration::from_secs(1);
+        assert!(!adjust_resolution(
+            &mut settings,
+            &mut cost,
+            COLS * ROWS,
+            slow
+        ));
+        assert!(cost.is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::lighting::Rng;
+use crate::scene::{Cuboid, Object, Sphere};
+use notan::math::{Mat3, Quat, Vec3};
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+
+// Procedural scenes with any number of objects, for stress testing the
+// tracer without writing scene files by hand. Everything is laid out in front
+// of the camera's starting position, looking down +z.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Generator {
+    // Randomly placed spheres filling a cube that grows with the count.
+    Spheres,
+    // The sphere flake fractal: every sphere carries nine smaller ones.
+    Flake,
+    // A square grid of boxes of random heights on the ground.
+    Boxes,
+}
+
+pub const GENERATORS: [Generator; 3] = [Generator::Spheres, Generator::Flake, Generator::Boxes];
+
+impl Generator {
+    pub fn parse(name: &str) -> Option<Self> {
+        GENERATORS.into_iter().find(|g| g.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Generator::Spheres => "spheres",
+            Generator::Flake => "flake",
+            Generator::Boxes => "boxes",
+        }
+    }
+}
+
+// Exactly `count` objects, the same every time for the same seed.
+pub fn generate(generator: Generator, seed: u64, count: usize) -> Vec<Object> {
+    let mut rng = Rng::new(seed);
+    match generator {
+        Generator::Spheres => sphere_field(&mut rng, count),
+        Generator::Flake => sphere_flake(&mut rng, count),
+        Generator::Boxes => box_grid(&mut rng, count),
+    }
+}
+
+fn sphere_field(rng: &mut Rng, count: usize) -> Vec<Object> {
+    // Roughly one sphere per 1.5 units cubed, however many there are.
+    let extent = (count as f32).cbrt() * 1.5 / 2.0;
+
+    (0..count)
+        .map(|_| {
+            let cube = Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 2.0 - 1.0;
+            Object::Sphere(Sphere {
+                center: cube * extent + Vec3::new(0.0, 0.0, extent + 3.0),
+                radius: 0.1 + rng.next_f32() * 0.4,
+                texture: None,
+                emission: 0.0,
+            })
+        })
+        .collect()
+}
+
+// Children sit around the equator and the upper latitudes of their parent,
+// facing away from the grandparent, each a third of its size.
+fn sphere_flake(rng: &mut Rng, count: usize) -> Vec<Object> {
+    let children: Vec<Vec3> = (0..6)
+        .map(|i| (i as f32 / 6.0 * TAU, 0.0))
+        .chain((0..3).map(|i| ((i as f32 + 0.5) / 3.0 * TAU, TAU / 6.0)))
+        .map(|(longitude, latitude): (f32, f32)| {
+            Vec3::new(
+                latitude.cos() * longitude.cos(),
+                latitude.sin(),
+                latitude.cos() * longitude.sin(),
+            )
+        })
+        .collect();
+
+    let mut objects = Vec::with_capacity(count);
+    let mut queue = VecDeque::from([(Vec3::new(0.0, 0.0, 9.0), 1.0, Vec3::Y)]);
+
+    // Breadth first, so a count that falls between levels leaves the last
+    // level partly filled rather than some branches much deeper than others.
+    while let Some((center, radius, axis)) = queue.pop_front() {
+        if objects.len() == count {
+            break;
+        }
+        objects.push(Object::Sphere(Sphere {
+            center,
+            radius,
+            texture: None,
+            emission: 0.0,
+        }));
+
+        let twist = Quat::from_rotation_y(rng.next_f32() * TAU);
+        let frame = Mat3::from_quat(Quat::from_rotation_arc(Vec3::Y, axis) * twist);
+        for &child in &children {
+            if objects.len() + queue.len() >= count {
+                break;
+            }
+            let direction = frame * child;
+            let child_radius = radius / 3.0;
+            queue.push_back((
+                center + direction * (radius + child_radius),
+                child_radius,
+                direction,
+            ));
+        }
+    }
+
+    objects
+}
+
+fn box_grid(rng: &mut Rng, count: usize) -> Vec<Object> {
+    let side = (count as f32).sqrt().ceil() as usize;
+    const SPACING: f32 = 1.0;
+
+    (0..count)
+        .map(|i| {
+            let (column, row) = (i % side, i / side);
+            // Low enough to see across the grid from the starting position.
+            let height = 0.05 + rng.next_f32() * 0.45;
+
+            Object::Cuboid(Cuboid {
+                center: Vec3::new(
+                    (column as f32 - (side - 1) as f32 / 2.0) * SPACING,
+                    -1.5 + height,
+                    2.0 + row as f32 * SPACING,
+                ),
+                half_extents: Vec3::new(0.3, height, 0.3),
+                rotation: Mat3::from_rotation_y(rng.next_f32() * TAU),
+                texture: None,
+                emission: 0.0,
+            })
+        })
+        .collect()
+}
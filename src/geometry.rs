@@ -0,0 +1,2150 @@
+use crate::scene::{
+    texture_albedo, Capsule, Csg, CsgOperation, Cuboid, Cylinder, Instance, Object, Plane,
+    SdfObject, Sphere, Terrain, Torus, Triangle,
+};
+use notan::math::Vec3;
+use std::cell::Cell;
+
+// Sphere tracing gives up after this many steps or once the ray has travelled
+// MARCH_DISTANCE, and counts anything closer than MARCH_EPSILON as a hit.
+pub const MARCH_STEPS: usize = 128;
+pub const MARCH_DISTANCE: f32 = 100.0;
+pub const MARCH_EPSILON: f32 = 1e-3;
+
+#[derive(Clone, Copy)]
+pub struct Hit {
+    pub t: f32,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+}
+
+// The stretch of a ray spent inside a solid. Normals at both ends point out of
+// the solid.
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub entry: Hit,
+    pub exit: Hit,
+}
+
+pub trait Hittable {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit>;
+
+    // Every span of the (unbounded) ray inside the primitive, sorted and
+    // disjoint. Surfaces without volume, like triangles, have none.
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span>;
+}
+
+pub fn surface(t: f32, normal: Vec3) -> Hit {
+    Hit {
+        t,
+        normal,
+        albedo: Vec3::ONE,
+    }
+}
+
+impl Sphere {
+    // Longitude and latitude around the center, with the poles on the y axis.
+    pub fn uv(&self, point: Vec3) -> (f32, f32) {
+        let n = (point - self.center) / self.radius;
+        (
+            0.5 + n.z.atan2(n.x) / std::f32::consts::TAU,
+            0.5 + n.y.clamp(-1.0, 1.0).asin() / std::f32::consts::PI,
+        )
+    }
+
+    pub fn hit(&self, t: f32, point: Vec3) -> Hit {
+        Hit {
+            t,
+            normal: (point - self.center) / self.radius,
+            albedo: texture_albedo(&self.texture, || self.uv(point)),
+        }
+    }
+}
+
+impl Cuboid {
+    // Each face is mapped over the whole texture, using the two axes the face
+    // spans in the cuboid's own frame.
+    pub fn uv(&self, point: Vec3) -> (f32, f32) {
+        let local = self.rotation.transpose() * (point - self.center) / self.half_extents;
+        let abs = local.abs();
+
+        let (u, v) = if abs.x >= abs.y && abs.x >= abs.z {
+            (local.z * -local.x.signum(), local.y)
+        } else if abs.y >= abs.z {
+            (local.x, local.z * -local.y.signum())
+        } else {
+            (local.x * local.z.signum(), local.y)
+        };
+
+        ((u + 1.0) / 2.0, (v + 1.0) / 2.0)
+    }
+}
+
+impl Terrain {
+    // Across the footprint, from (0, 0) at the near left corner to (1, 1) at
+    // the far right.
+    pub fn uv(&self, point: Vec3) -> (f32, f32) {
+        let offset = point - self.center;
+        (0.5 + offset.x / self.size, 0.5 + offset.z / self.size)
+    }
+}
+
+impl Triangle {
+    // Facing the side the vertices wind anticlockwise around.
+    pub fn normal(&self) -> Vec3 {
+        (self.vertex2 - self.vertex1)
+            .cross(self.vertex3 - self.vertex1)
+            .normalize()
+    }
+
+    // The vertex normals blended by the barycentric coordinates of a hit, or
+    // the face normal when there are none.
+    pub fn shading_normal(&self, u: f32, v: f32) -> Vec3 {
+        match self.normals {
+            Some([normal1, normal2, normal3]) => {
+                ((1.0 - u - v) * normal1 + u * normal2 + v * normal3)
+                    .try_normalize()
+                    .unwrap_or_else(|| self.normal())
+            }
+            None => self.normal(),
+        }
+    }
+}
+
+impl Hittable for Sphere {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let (t1, t2) = ray_intersects_sphere(origin, direction, self);
+
+        let t = [t1, t2]
+            .into_iter()
+            .filter(|t| t_min < *t && *t < t_max)
+            .reduce(f32::min)?;
+
+        Some(self.hit(t, origin + t * direction))
+    }
+
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        let (t1, t2) = ray_intersects_sphere(origin, direction, self);
+        if t1 == f32::INFINITY {
+            return Vec::new();
+        }
+
+        vec![Span {
+            entry: self.hit(t2, origin + t2 * direction),
+            exit: self.hit(t1, origin + t1 * direction),
+        }]
+    }
+}
+
+impl Hittable for Cuboid {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let (t, normal) = ray_intersects_cuboid(origin, direction, self)?;
+
+        (t_min < t && t < t_max).then(|| Hit {
+            t,
+            normal,
+            albedo: texture_albedo(&self.texture, || self.uv(origin + t * direction)),
+        })
+    }
+
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        let inverse = self.rotation.transpose();
+        let local_origin = inverse * (origin - self.center);
+        let local_direction = inverse * direction;
+
+        let Some((entry, exit)) = cuboid_span(
+            local_origin,
+            local_direction,
+            Vec3::default(),
+            self.half_extents,
+        ) else {
+            return Vec::new();
+        };
+
+        let hit = |t: f32| {
+            let point = local_origin + t * local_direction;
+            Hit {
+                t,
+                normal: self.rotation
+                    * compute_cuboid_normal(point, Vec3::default(), self.half_extents),
+                albedo: texture_albedo(&self.texture, || self.uv(origin + t * direction)),
+            }
+        };
+        vec![Span {
+            entry: hit(entry),
+            exit: hit(exit),
+        }]
+    }
+}
+
+impl Hittable for Triangle {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let (t, u, v) = ray_intersects_triangle(origin, direction, self)?;
+
+        (t_min < t && t < t_max).then(|| Hit {
+            t,
+            normal: self.shading_normal(u, v),
+            albedo: texture_albedo(&self.texture, || (u, v)),
+        })
+    }
+
+    fn spans(&self, _origin: Vec3, _direction: Vec3) -> Vec<Span> {
+        Vec::new()
+    }
+}
+
+impl Hittable for Plane {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let (t, normal) = ray_intersects_plane(origin, direction, self)?;
+
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        let albedo = self.albedo(origin + t * direction);
+        Some(Hit { t, normal, albedo })
+    }
+
+    // As a solid the plane is the half space behind its normal.
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        let behind = (origin - self.point).dot(self.normal) < 0.0;
+
+        let Some((t, _)) = ray_intersects_plane(origin, direction, self) else {
+            return if behind {
+                vec![Span {
+                    entry: surface(f32::NEG_INFINITY, self.normal),
+                    exit: surface(f32::INFINITY, self.normal),
+                }]
+            } else {
+                Vec::new()
+            };
+        };
+
+        let boundary = Hit {
+            t,
+            normal: self.normal,
+            albedo: self.albedo(origin + t * direction),
+        };
+
+        if direction.dot(self.normal) < 0.0 {
+            vec![Span {
+                entry: boundary,
+                exit: surface(f32::INFINITY, self.normal),
+            }]
+        } else {
+            vec![Span {
+                entry: surface(f32::NEG_INFINITY, self.normal),
+                exit: boundary,
+            }]
+        }
+    }
+}
+
+impl Plane {
+    pub fn albedo(&self, point: Vec3) -> Vec3 {
+        let Some(size) = self.checker else {
+            return Vec3::ONE;
+        };
+
+        let local = point - self.point;
+        let n = self.normal.normalize();
+        let u = n.any_orthonormal_vector();
+        let v = n.cross(u);
+
+        let parity = (local.dot(u) / size).floor() + (local.dot(v) / size).floor();
+        if parity.rem_euclid(2.0) < 1.0 {
+            Vec3::ONE
+        } else {
+            Vec3::splat(0.5)
+        }
+    }
+}
+
+// Picks the first surface of an (entry, exit) span that lies inside the ray
+// range, which is the exit when the ray starts inside the solid.
+pub fn nearest_in_span(span: (f32, f32), t_min: f32, t_max: f32) -> Option<f32> {
+    let (entry, exit) = span;
+
+    [entry, exit].into_iter().find(|t| t_min < *t && *t < t_max)
+}
+
+impl Hittable for Cylinder {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let span = ray_intersects_cylinder(origin, direction, self)?;
+        let t = nearest_in_span(span, t_min, t_max)?;
+
+        Some(Hit {
+            t,
+            normal: compute_cylinder_normal(origin + t * direction, self),
+            albedo: Vec3::ONE,
+        })
+    }
+
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        let Some((entry, exit)) = ray_intersects_cylinder(origin, direction, self) else {
+            return Vec::new();
+        };
+
+        let normal = |t: f32| compute_cylinder_normal(origin + t * direction, self);
+        vec![Span {
+            entry: surface(entry, normal(entry)),
+            exit: surface(exit, normal(exit)),
+        }]
+    }
+}
+
+impl Hittable for Capsule {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let span = ray_intersects_capsule(origin, direction, self)?;
+        let t = nearest_in_span(span, t_min, t_max)?;
+
+        let p = origin + t * direction;
+        Some(Hit {
+            t,
+            normal: p - closest_point_on_segment(p, self.start, self.end),
+            albedo: Vec3::ONE,
+        })
+    }
+
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        let Some((entry, exit)) = ray_intersects_capsule(origin, direction, self) else {
+            return Vec::new();
+        };
+
+        let normal = |t: f32| {
+            let p = origin + t * direction;
+            p - closest_point_on_segment(p, self.start, self.end)
+        };
+        vec![Span {
+            entry: surface(entry, normal(entry)),
+            exit: surface(exit, normal(exit)),
+        }]
+    }
+}
+
+impl Hittable for Torus {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let t = ray_intersects_torus(origin, direction, self)
+            .into_iter()
+            .find(|t| t_min < *t && *t < t_max)?;
+
+        Some(Hit {
+            t,
+            normal: compute_torus_normal(origin + t * direction, self),
+            albedo: Vec3::ONE,
+        })
+    }
+
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        let normal = |t: f32| compute_torus_normal(origin + t * direction, self);
+
+        ray_intersects_torus(origin, direction, self)
+            .chunks_exact(2)
+            .map(|pair| Span {
+                entry: surface(pair[0], normal(pair[0])),
+                exit: surface(pair[1], normal(pair[1])),
+            })
+            .collect()
+    }
+}
+
+// Terrain is a surface rather than a solid, open underneath and at the sides.
+impl Hittable for Terrain {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let (t, normal) = ray_intersects_terrain(origin, direction, self, t_min, t_max)?;
+        Some(surface(t, normal))
+    }
+
+    fn spans(&self, _origin: Vec3, _direction: Vec3) -> Vec<Span> {
+        Vec::new()
+    }
+}
+
+impl Hittable for SdfObject {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let length = direction.length();
+        let t = sphere_trace(
+            origin,
+            direction / length,
+            t_min * length,
+            t_max * length,
+            self,
+        )?;
+
+        Some(Hit {
+            t: t / length,
+            normal: compute_sdf_normal(origin + t / length * direction, self),
+            albedo: Vec3::ONE,
+        })
+    }
+
+    // Marches forwards from the ray origin, alternating between searching for
+    // the next surface from outside and from inside. Spans can only be found
+    // in front of the origin, which is all a CSG node ever looks at.
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        let length = direction.length();
+        let direction_n = direction / length;
+        let normal = |t: f32| compute_sdf_normal(origin + t * direction_n, self);
+
+        let mut spans = Vec::new();
+        let mut t = 0.0;
+        let mut entry = (self.distance(origin) < 0.0).then_some(f32::NEG_INFINITY);
+
+        for _ in 0..MARCH_STEPS {
+            if t >= MARCH_DISTANCE {
+                break;
+            }
+            ITERATIONS.set(ITERATIONS.get() + 1);
+
+            let distance = self.distance(origin + t * direction_n);
+
+            match entry {
+                None if distance < MARCH_EPSILON => entry = Some(t),
+                Some(start) if distance > -MARCH_EPSILON => {
+                    spans.push(Span {
+                        entry: surface(start / length, normal(start)),
+                        exit: surface(t / length, normal(t)),
+                    });
+                    entry = None;
+                }
+                _ => {}
+            }
+
+            // Step past the surface once it has been recorded.
+            t += distance.abs().max(MARCH_EPSILON);
+        }
+
+        if let Some(start) = entry {
+            spans.push(Span {
+                entry: surface(start / length, normal(start)),
+                exit: surface(f32::INFINITY, direction_n),
+            });
+        }
+
+        spans
+    }
+}
+
+impl Hittable for Csg {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        self.spans(origin, direction)
+            .into_iter()
+            .flat_map(|span| [span.entry, span.exit])
+            .find(|hit| t_min < hit.t && hit.t < t_max)
+    }
+
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        // The local frame is only rotated and uniformly scaled, so distances
+        // along the ray carry over unchanged.
+        let inverse = self.rotation.transpose();
+        let local_origin = inverse * (origin - self.position) / self.scale;
+        let local_direction = inverse * direction / self.scale;
+
+        let a = self.a.spans(local_origin, local_direction);
+        let b = self.b.spans(local_origin, local_direction);
+
+        let mut spans = match self.operation {
+            CsgOperation::Union => combine_spans(&a, &b, |a, b| a || b, false),
+            CsgOperation::Intersection => combine_spans(&a, &b, |a, b| a && b, false),
+            CsgOperation::Difference => combine_spans(&a, &b, |a, b| a && !b, true),
+        };
+
+        for span in &mut spans {
+            span.entry.normal = self.rotation * span.entry.normal;
+            span.exit.normal = self.rotation * span.exit.normal;
+        }
+
+        spans
+    }
+}
+
+impl Instance {
+    // The ray in the mesh's own frame. As with CSG nodes the frame is only
+    // rotated and uniformly scaled, so distances along the ray carry over.
+    pub fn local_ray(&self, origin: Vec3, direction: Vec3) -> (Vec3, Vec3) {
+        let inverse = self.rotation.transpose();
+        (
+            inverse * (origin - self.position) / self.scale,
+            inverse * direction / self.scale,
+        )
+    }
+}
+
+impl Hittable for Instance {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        let (origin, direction) = self.local_ray(origin, direction);
+        ITERATIONS.set(ITERATIONS.get() + self.objects.len());
+
+        let mut closest: Option<Hit> = None;
+        for object in self.objects.iter() {
+            let closest_t = closest.map_or(t_max, |hit| hit.t);
+            if let Some(hit) = object.intersect(origin, direction, t_min, closest_t) {
+                closest = Some(hit);
+            }
+        }
+
+        closest.map(|hit| Hit {
+            normal: self.rotation * hit.normal,
+            ..hit
+        })
+    }
+
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        let (origin, direction) = self.local_ray(origin, direction);
+
+        let mut spans = self.objects.iter().fold(Vec::new(), |spans, object| {
+            combine_spans(
+                &spans,
+                &object.spans(origin, direction),
+                |a, b| a || b,
+                false,
+            )
+        });
+        for span in &mut spans {
+            span.entry.normal = self.rotation * span.entry.normal;
+            span.exit.normal = self.rotation * span.exit.normal;
+        }
+
+        spans
+    }
+}
+
+// Sweeps the boundaries of both span lists in order, tracking whether the ray
+// is inside each operand, and records every point where `inside` changes.
+// Surfaces taken from `b` face the other way when it is being carved out.
+pub fn combine_spans(
+    a: &[Span],
+    b: &[Span],
+    inside: fn(bool, bool) -> bool,
+    flip_b: bool,
+) -> Vec<Span> {
+    let mut events: Vec<(Hit, bool, bool)> = Vec::with_capacity(2 * (a.len() + b.len()));
+    for (spans, from_a) in [(a, true), (b, false)] {
+        for span in spans {
+            events.push((span.entry, from_a, true));
+            events.push((span.exit, from_a, false));
+        }
+    }
+    events.sort_by(|x, y| x.0.t.total_cmp(&y.0.t));
+
+    let (mut in_a, mut in_b) = (false, false);
+    let mut entry: Option<Hit> = None;
+    let mut spans = Vec::new();
+
+    for (mut hit, from_a, entering) in events {
+        if from_a {
+            in_a = entering;
+        } else {
+            in_b = entering;
+            if flip_b {
+                hit.normal = -hit.normal;
+            }
+        }
+
+        match (entry, inside(in_a, in_b)) {
+            (None, true) => entry = Some(hit),
+            (Some(start), false) => {
+                spans.push(Span {
+                    entry: start,
+                    exit: hit,
+                });
+                entry = None;
+            }
+            _ => {}
+        }
+    }
+
+    spans
+}
+
+impl Hittable for Object {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<Hit> {
+        match self {
+            Object::Sphere(sphere) => sphere.intersect(origin, direction, t_min, t_max),
+            Object::Cuboid(cuboid) => cuboid.intersect(origin, direction, t_min, t_max),
+            Object::Triangle(triangle) => triangle.intersect(origin, direction, t_min, t_max),
+            Object::Plane(plane) => plane.intersect(origin, direction, t_min, t_max),
+            Object::Cylinder(cylinder) => cylinder.intersect(origin, direction, t_min, t_max),
+            Object::Capsule(capsule) => capsule.intersect(origin, direction, t_min, t_max),
+            Object::Torus(torus) => torus.intersect(origin, direction, t_min, t_max),
+            Object::Terrain(terrain) => terrain.intersect(origin, direction, t_min, t_max),
+            Object::Sdf(sdf) => sdf.intersect(origin, direction, t_min, t_max),
+            Object::Csg(csg) => csg.intersect(origin, direction, t_min, t_max),
+            Object::Instance(instance) => instance.intersect(origin, direction, t_min, t_max),
+        }
+    }
+
+    fn spans(&self, origin: Vec3, direction: Vec3) -> Vec<Span> {
+        match self {
+            Object::Sphere(sphere) => sphere.spans(origin, direction),
+            Object::Cuboid(cuboid) => cuboid.spans(origin, direction),
+            Object::Triangle(triangle) => triangle.spans(origin, direction),
+            Object::Plane(plane) => plane.spans(origin, direction),
+            Object::Cylinder(cylinder) => cylinder.spans(origin, direction),
+            Object::Capsule(capsule) => capsule.spans(origin, direction),
+            Object::Torus(torus) => torus.spans(origin, direction),
+            Object::Terrain(terrain) => terrain.spans(origin, direction),
+            Object::Sdf(sdf) => sdf.spans(origin, direction),
+            Object::Csg(csg) => csg.spans(origin, direction),
+            Object::Instance(instance) => instance.spans(origin, direction),
+        }
+    }
+}
+
+// Möller–Trumbore: solves origin + t * direction = vertex1 + u * edge1 +
+// v * edge2 directly, giving the distance and the barycentric weights of the
+// second and third vertices. Triangles are hit from either side.
+pub fn ray_intersects_triangle(
+    origin: Vec3,
+    direction: Vec3,
+    triangle: &Triangle,
+) -> Option<(f32, f32, f32)> {
+    ray_intersects_vertices(
+        origin,
+        direction,
+        [triangle.vertex1, triangle.vertex2, triangle.vertex3],
+    )
+}
+
+pub fn ray_intersects_vertices(
+    origin: Vec3,
+    direction: Vec3,
+    [vertex1, vertex2, vertex3]: [Vec3; 3],
+) -> Option<(f32, f32, f32)> {
+    const EPSILON: f32 = 1e-6;
+
+    let edge1 = vertex2 - vertex1;
+    let edge2 = vertex3 - vertex1;
+
+    // Zero when the ray runs parallel to the triangle, or the triangle is
+    // degenerate and has no plane at all.
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+    let inverse = determinant.recip();
+
+    let offset = origin - vertex1;
+    let u = offset.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = offset.cross(edge1);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inverse;
+    (t > EPSILON).then_some((t, u, v))
+}
+
+// Walks the ray across the height field's grid a cell at a time, from where
+// it enters the terrain's bounding box, testing the two triangles spanning
+// each cell it crosses. The first cell with a hit holds the nearest one.
+// Returns the distance and the upward facing normal.
+pub fn ray_intersects_terrain(
+    origin: Vec3,
+    direction: Vec3,
+    terrain: &Terrain,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, Vec3)> {
+    let (columns, rows) = terrain.heightmap.size();
+    if columns < 2 || rows < 2 {
+        return None;
+    }
+
+    let half = Vec3::new(terrain.size, terrain.height, terrain.size) / 2.0;
+    let (enter, exit) = cuboid_span(origin, direction, terrain.center + Vec3::Y * half.y, half)?;
+    let (enter, exit) = (enter.max(t_min), exit.min(t_max));
+    if enter > exit {
+        return None;
+    }
+
+    let corner = terrain.center - Vec3::new(half.x, 0.0, half.z);
+    let cell = (
+        terrain.size / (columns - 1) as f32,
+        terrain.size / (rows - 1) as f32,
+    );
+    let vertex = |column: usize, row: usize| {
+        corner
+            + Vec3::new(
+                column as f32 * cell.0,
+                terrain.heightmap.height(column, row) * terrain.height,
+                row as f32 * cell.1,
+            )
+    };
+
+    // The cell holding the entry point, and the distances along the ray at
+    // which it next crosses a grid line in x and in z.
+    let start = origin + enter * direction;
+    let mut column =
+        (((start.x - corner.x) / cell.0).floor() as isize).clamp(0, columns as isize - 2);
+    let mut row = (((start.z - corner.z) / cell.1).floor() as isize).clamp(0, rows as isize - 2);
+
+    let crossing = |along: f32, from: f32, index: isize, size: f32| {
+        if along == 0.0 {
+            return (f32::INFINITY, f32::INFINITY);
+        }
+        let edge = if along > 0.0 { index + 1 } else { index } as f32 * size;
+        ((edge - from) / along, size / along.abs())
+    };
+    let (mut next_x, step_x) = crossing(direction.x, origin.x - corner.x, column, cell.0);
+    let (mut next_z, step_z) = crossing(direction.z, origin.z - corner.z, row, cell.1);
+
+    let mut cell_enter = enter;
+    loop {
+        ITERATIONS.set(ITERATIONS.get() + 1);
+        let (x, z) = (column as usize, row as usize);
+        let cell_exit = next_x.min(next_z).min(exit);
+
+        // Nothing to hit while the ray stays above the cell's highest corner.
+        let corners = [
+            vertex(x, z),
+            vertex(x + 1, z),
+            vertex(x, z + 1),
+            vertex(x + 1, z + 1),
+        ];
+        let highest = corners
+            .iter()
+            .map(|v| v.y)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let lowest_ray =
+            (origin.y + cell_enter * direction.y).min(origin.y + cell_exit * direction.y);
+
+        if lowest_ray <= highest {
+            let [a, b, c, d] = corners;
+            let hit = [[a, b, d], [a, d, c]]
+                .into_iter()
+                .filter_map(|face| {
+                    let (t, _, _) = ray_intersects_vertices(origin, direction, face)?;
+                    (t_min < t && t < t_max).then_some((t, face))
+                })
+                .min_by(|x, y| x.0.total_cmp(&y.0));
+
+            if let Some((t, [a, b, c])) = hit {
+                let normal = (b - a).cross(c - a).normalize();
+                let normal = if normal.y < 0.0 { -normal } else { normal };
+                return Some((t, normal));
+            }
+        }
+
+        if cell_exit >= exit {
+            return None;
+        }
+        cell_enter = cell_exit;
+        if next_x < next_z {
+            column += direction.x.signum() as isize;
+            next_x += step_x;
+        } else {
+            row += direction.z.signum() as isize;
+            next_z += step_z;
+        }
+        if column < 0 || row < 0 || column > columns as isize - 2 || row > rows as isize - 2 {
+            return None;
+        }
+    }
+}
+
+// Slab test returning where the unbounded ray enters and leaves the box.
+pub fn cuboid_span(
+    origin: Vec3,
+    direction: Vec3,
+    position: Vec3,
+    half_extents: Vec3,
+) -> Option<(f32, f32)> {
+    let inv_direction = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+
+    let t1 = (position - half_extents - origin) * inv_direction;
+    let t2 = (position + half_extents - origin) * inv_direction;
+
+    let tmin = t1.min(t2);
+    let tmax = t1.max(t2);
+
+    let t_enter = tmin.max_element();
+    let t_exit = tmax.min_element();
+
+    (t_enter <= t_exit).then_some((t_enter, t_exit))
+}
+
+pub fn ray_intersects_cuboid_no_rotation(
+    origin: Vec3,
+    direction: Vec3,
+    position: Vec3,
+    half_extents: Vec3,
+) -> Option<(f32, Vec3)> {
+    let (t_enter, t_exit) = cuboid_span(origin, direction, position, half_extents)?;
+
+    if t_exit < 0.0 {
+        return None; // Box is behind the ray origin
+    }
+
+    // When the origin is inside the box the only visible face is the exit.
+    let t = if t_enter < 0.0 { t_exit } else { t_enter };
+
+    let intersection_point = origin + direction * t;
+    let normal = compute_cuboid_normal(intersection_point, position, half_extents);
+
+    Some((t, normal))
+}
+
+// Oriented boxes are intersected in their own frame, where they are axis
+// aligned, and the normal is rotated back into world space.
+pub fn ray_intersects_cuboid(
+    origin: Vec3,
+    direction: Vec3,
+    cuboid: &Cuboid,
+) -> Option<(f32, Vec3)> {
+    let inverse = cuboid.rotation.transpose();
+
+    let local_origin = inverse * (origin - cuboid.center);
+    let local_direction = inverse * direction;
+
+    let (t, normal) = ray_intersects_cuboid_no_rotation(
+        local_origin,
+        local_direction,
+        Vec3::default(),
+        cuboid.half_extents,
+    )?;
+
+    Some((t, cuboid.rotation * normal))
+}
+
+pub fn compute_cuboid_normal(point: Vec3, position: Vec3, half_extents: Vec3) -> Vec3 {
+    let local_point = point - position;
+    let mut normal = Vec3::default();
+
+    for i in 0..3 {
+        if local_point[i].abs() + 1e-6 > half_extents[i] {
+            normal[i] = local_point[i].signum();
+        }
+    }
+
+    normal
+}
+
+// Planes are two sided, so the returned normal always faces the ray.
+pub fn ray_intersects_plane(origin: Vec3, direction: Vec3, plane: &Plane) -> Option<(f32, Vec3)> {
+    const EPSILON: f32 = 1e-6;
+
+    let denominator = direction.dot(plane.normal);
+    if denominator.abs() < EPSILON {
+        return None; // Ray is parallel to the plane
+    }
+
+    let t = (plane.point - origin).dot(plane.normal) / denominator;
+
+    let normal = if denominator > 0.0 {
+        -plane.normal
+    } else {
+        plane.normal
+    };
+
+    Some((t, normal))
+}
+
+pub fn ray_intersects_sphere(origin: Vec3, direction: Vec3, sphere: &Sphere) -> (f32, f32) {
+    let r = sphere.radius;
+
+    let co = origin - sphere.center;
+
+    let a = direction.dot(direction);
+    let b = 2.0 * co.dot(direction);
+    let c = co.dot(co) - r * r;
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return (f32::INFINITY, f32::INFINITY);
+    }
+
+    let t1 = (-b + discriminant.sqrt()) / (2.0 * a);
+    let t2 = (-b - discriminant.sqrt()) / (2.0 * a);
+
+    (t1, t2)
+}
+
+// Returns the (entry, exit) distances along the ray through the finite, capped
+// cylinder: the span inside the infinite tube clipped to the slab between caps.
+pub fn ray_intersects_cylinder(
+    origin: Vec3,
+    direction: Vec3,
+    cylinder: &Cylinder,
+) -> Option<(f32, f32)> {
+    const EPSILON: f32 = 1e-6;
+
+    let axis = cylinder.end - cylinder.start;
+    let height = axis.length();
+    let axis = axis / height;
+
+    let oc = origin - cylinder.start;
+    let (o_axial, d_axial) = (oc.dot(axis), direction.dot(axis));
+    let (o_radial, d_radial) = (oc - o_axial * axis, direction - d_axial * axis);
+
+    let a = d_radial.dot(d_radial);
+    let b = 2.0 * o_radial.dot(d_radial);
+    let c = o_radial.dot(o_radial) - cylinder.radius * cylinder.radius;
+
+    let (mut entry, mut exit) = if a < EPSILON {
+        // Ray runs parallel to the axis, either inside the tube or not at all
+        if c > 0.0 {
+            return None;
+        }
+        (f32::NEG_INFINITY, f32::INFINITY)
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        (
+            (-b - discriminant.sqrt()) / (2.0 * a),
+            (-b + discriminant.sqrt()) / (2.0 * a),
+        )
+    };
+
+    if d_axial.abs() < EPSILON {
+        if o_axial < 0.0 || o_axial > height {
+            return None;
+        }
+    } else {
+        let t1 = -o_axial / d_axial;
+        let t2 = (height - o_axial) / d_axial;
+        entry = entry.max(t1.min(t2));
+        exit = exit.min(t1.max(t2));
+    }
+
+    (entry <= exit).then_some((entry, exit))
+}
+
+pub fn compute_cylinder_normal(point: Vec3, cylinder: &Cylinder) -> Vec3 {
+    let axis = cylinder.end - cylinder.start;
+    let height = axis.length();
+    let axis = axis / height;
+
+    let along = (point - cylinder.start).dot(axis);
+    let radial = point - cylinder.start - along * axis;
+
+    if along < 1e-4 {
+        -axis
+    } else if along > height - 1e-4 {
+        axis
+    } else {
+        radial
+    }
+}
+
+pub fn closest_point_on_segment(point: Vec3, start: Vec3, end: Vec3) -> Vec3 {
+    let segment = end - start;
+    let f = ((point - start).dot(segment) / segment.length_squared()).clamp(0.0, 1.0);
+
+    start + f * segment
+}
+
+// A capsule is convex, so its span is the union of the spans through the tube
+// and the two end spheres.
+pub fn ray_intersects_capsule(
+    origin: Vec3,
+    direction: Vec3,
+    capsule: &Capsule,
+) -> Option<(f32, f32)> {
+    let tube = Cylinder {
+        start: capsule.start,
+        end: capsule.end,
+        radius: capsule.radius,
+        emission: 0.0,
+    };
+
+    let ends = [capsule.start, capsule.end].map(|center| {
+        let (t1, t2) = ray_intersects_sphere(
+            origin,
+            direction,
+            &Sphere {
+                center,
+                radius: capsule.radius,
+                texture: None,
+                emission: 0.0,
+            },
+        );
+        (t1 != f32::INFINITY).then_some((t1.min(t2), t1.max(t2)))
+    });
+
+    [
+        ray_intersects_cylinder(origin, direction, &tube),
+        ends[0],
+        ends[1],
+    ]
+    .into_iter()
+    .flatten()
+    .reduce(|(a1, b1), (a2, b2)| (a1.min(a2), b1.max(b2)))
+}
+
+// Returns every distance at which the ray crosses the torus surface, sorted,
+// so consecutive pairs are the spans inside the tube.
+pub fn ray_intersects_torus(origin: Vec3, direction: Vec3, torus: &Torus) -> Vec<f32> {
+    let (major, minor) = (torus.major_radius, torus.minor_radius);
+
+    // Cheap rejection against the bounding sphere before solving the quartic.
+    let (t1, _) = ray_intersects_sphere(
+        origin,
+        direction,
+        &Sphere {
+            center: torus.center,
+            radius: major + minor,
+            texture: None,
+            emission: 0.0,
+        },
+    );
+    if t1 == f32::INFINITY {
+        return Vec::new();
+    }
+
+    let inverse = torus.rotation.transpose();
+    let o = (inverse * (origin - torus.center)).as_dvec3();
+    let d = (inverse * direction).as_dvec3();
+    let (major, minor) = (major as f64, minor as f64);
+
+    let dd = d.dot(d);
+    let od = o.dot(d);
+    let sum = o.dot(o) + major * major - minor * minor;
+    let r2 = 4.0 * major * major;
+
+    let mut roots = solve_quartic([
+        sum * sum - r2 * (o.x * o.x + o.z * o.z),
+        4.0 * od * sum - 2.0 * r2 * (o.x * d.x + o.z * d.z),
+        2.0 * dd * sum + 4.0 * od * od - r2 * (d.x * d.x + d.z * d.z),
+        4.0 * dd * od,
+        dd * dd,
+    ]);
+
+    roots.sort_by(f64::total_cmp);
+    roots.into_iter().map(|t| t as f32).collect()
+}
+
+pub fn compute_torus_normal(point: Vec3, torus: &Torus) -> Vec3 {
+    let p = torus.rotation.transpose() * (point - torus.center);
+    let (major, minor) = (torus.major_radius, torus.minor_radius);
+
+    let k = p.length_squared() + major * major - minor * minor;
+    let n = k * p - 2.0 * major * major * Vec3::new(p.x, 0.0, p.z);
+
+    torus.rotation * n
+}
+
+// Steps along a normalised ray by the distance to the nearest surface, which is
+// always safe, until that distance becomes negligible.
+pub fn sphere_trace(
+    origin: Vec3,
+    direction: Vec3,
+    t_min: f32,
+    t_max: f32,
+    sdf: &SdfObject,
+) -> Option<f32> {
+    let mut t = t_min;
+
+    for _ in 0..MARCH_STEPS {
+        if t >= t_max.min(MARCH_DISTANCE) {
+            return None;
+        }
+        ITERATIONS.set(ITERATIONS.get() + 1);
+
+        let distance = sdf.distance(origin + t * direction);
+        if distance < MARCH_EPSILON {
+            return Some(t);
+        }
+
+        t += distance;
+    }
+
+    None
+}
+
+pub fn compute_sdf_normal(point: Vec3, sdf: &SdfObject) -> Vec3 {
+    const H: f32 = 1e-3;
+
+    let dx = Vec3::new(H, 0.0, 0.0);
+    let dy = Vec3::new(0.0, H, 0.0);
+    let dz = Vec3::new(0.0, 0.0, H);
+
+    Vec3::new(
+        sdf.distance(point + dx) - sdf.distance(point - dx),
+        sdf.distance(point + dy) - sdf.distance(point - dy),
+        sdf.distance(point + dz) - sdf.distance(point - dz),
+    )
+}
+
+// Real roots of c[0] + c[1] x + c[2] x^2, following the classic closed forms
+// from Graphics Gems.
+pub fn solve_quadratic(c: [f64; 3]) -> Vec<f64> {
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let discriminant = p * p - q;
+
+    if discriminant.abs() < 1e-9 {
+        vec![-p]
+    } else if discriminant < 0.0 {
+        Vec::new()
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![sqrt_d - p, -sqrt_d - p]
+    }
+}
+
+pub fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let c = c[0] / c[3];
+
+    // Substitute x = y - a/3 to get y^3 + p y + q = 0
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + c) / 2.0;
+
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let roots = if discriminant.abs() < 1e-9 {
+        if q.abs() < 1e-9 {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        let phi = (-q / (-cb_p).sqrt()).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + std::f64::consts::FRAC_PI_3).cos(),
+            -t * (phi - std::f64::consts::FRAC_PI_3).cos(),
+        ]
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+
+    roots.into_iter().map(|y| y - a / 3.0).collect()
+}
+
+pub fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let d = c[0] / c[4];
+    let c = c[1] / c[4];
+
+    // Substitute x = y - a/4 to get y^4 + p y^2 + q y + r = 0
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + c;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * c / 4.0 + d;
+
+    let roots = if r.abs() < 1e-9 {
+        let mut roots = solve_cubic([q, p, 0.0, 1.0]);
+        roots.push(0.0);
+        roots
+    } else {
+        // One real root of the resolvent cubic splits the quartic into two quadratics
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        if u < -1e-9 || v < -1e-9 {
+            return Vec::new();
+        }
+        let u = u.max(0.0).sqrt();
+        let v = v.max(0.0).sqrt().copysign(q);
+
+        let mut roots = solve_quadratic([z - u, v, 1.0]);
+        roots.extend(solve_quadratic([z + u, -v, 1.0]));
+        roots
+    };
+
+    roots.into_iter().map(|y| y - a / 4.0).collect()
+}
+
+// Texture coordinates of a hit at `t` along the ray, for the primitives that
+// have them.
+pub fn surface_uv(object: &Object, origin: Vec3, direction: Vec3, t: f32) -> Option<(f32, f32)> {
+    let point = origin + t * direction;
+    match object {
+        Object::Sphere(sphere) => Some(sphere.uv(point)),
+        Object::Cuboid(cuboid) => Some(cuboid.uv(point)),
+        Object::Triangle(triangle) => {
+            ray_intersects_triangle(origin, direction, triangle).map(|(_, u, v)| (u, v))
+        }
+        Object::Terrain(terrain) => Some(terrain.uv(point)),
+        _ => None,
+    }
+}
+
+// Rays cast and the work they took, for the HUD.
+#[derive(Clone, Copy, Default)]
+pub struct RayCounts {
+    pub rays: usize,
+    pub tests: usize,
+    // Shadow and bounce rays.
+    pub secondary: usize,
+}
+
+impl std::ops::AddAssign for RayCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.rays += other.rays;
+        self.tests += other.tests;
+        self.secondary += other.secondary;
+    }
+}
+
+thread_local! {
+    // Intersection tests and march steps taken on this thread, for the
+    // iteration count debug view to read back around a single ray.
+    pub static ITERATIONS: Cell<usize> = const { Cell::new(0) };
+
+    // What's been cast on this thread inside the innermost call to counting.
+    static COUNTS: Cell<RayCounts> = const {
+        Cell::new(RayCounts {
+            rays: 0,
+            tests: 0,
+            secondary: 0,
+        })
+    };
+}
+
+// Runs `f` and returns what it cast along with its result. Counts are kept
+// per thread, so each piece of parallel work counts its own and traces
+// running at the same time, like a screenshot during a frame, don't mix.
+pub fn counting<T>(f: impl FnOnce() -> T) -> (T, RayCounts) {
+    let outer = COUNTS.replace(RayCounts::default());
+    let result = f();
+    (result, COUNTS.replace(outer))
+}
+
+pub fn count(counts: RayCounts) {
+    let mut total = COUNTS.get();
+    total += counts;
+    COUNTS.set(total);
+}
+
+pub fn closest_hit(
+    origin: Vec3,
+    direction: Vec3,
+    t_min: f32,
+    t_max: f32,
+    objects: &[Object],
+) -> Option<(usize, Hit)> {
+    count(RayCounts {
+        rays: 1,
+        tests: objects.len(),
+        secondary: 0,
+    });
+    ITERATIONS.set(ITERATIONS.get() + objects.len());
+
+    let mut closest: Option<(usize, Hit)> = None;
+
+    for (i, object) in objects.iter().enumerate() {
+        let closest_t = closest.as_ref().map_or(t_max, |(_, hit)| hit.t);
+
+        if let Some(hit) = object.intersect(origin, direction, t_min, closest_t) {
+            closest = Some((i, hit));
+        }
+    }
+
+    closest
+}
+
+// Rays are traced in packets of this many, the width of an SSE register.
+pub const LANES: usize = 4;
+
+// A packet of rays stored component by component, so that the same operation
+// on every lane compiles down to a single SIMD instruction. Stable Rust has no
+// std::simd, and glam's Vec4 has no square root, so this leans on the
+// compiler; the spheres_scalar and spheres_packet benchmarks show whether it
+// still does.
+pub struct RayPacket {
+    origin: [[f32; LANES]; 3],
+    direction: [[f32; LANES]; 3],
+}
+
+impl RayPacket {
+    pub fn new(rays: &[(Vec3, Vec3); LANES]) -> Self {
+        let mut packet = RayPacket {
+            origin: [[0.0; LANES]; 3],
+            direction: [[0.0; LANES]; 3],
+        };
+        for (lane, (origin, direction)) in rays.iter().enumerate() {
+            for axis in 0..3 {
+                packet.origin[axis][lane] = origin[axis];
+                packet.direction[axis][lane] = direction[axis];
+            }
+        }
+        packet
+    }
+
+    pub fn ray(&self, lane: usize) -> (Vec3, Vec3) {
+        let [ox, oy, oz] = self.origin.map(|axis| axis[lane]);
+        let [dx, dy, dz] = self.direction.map(|axis| axis[lane]);
+        (Vec3::new(ox, oy, oz), Vec3::new(dx, dy, dz))
+    }
+}
+
+// closest_hit for every ray in a packet. Spheres, the most common primitive,
+// are tested against all lanes at once; anything else falls back to one ray
+// at a time.
+pub fn closest_hits(
+    packet: &RayPacket,
+    t_min: f32,
+    t_max: f32,
+    objects: &[Object],
+) -> [Option<(usize, Hit)>; LANES] {
+    count(RayCounts {
+        rays: LANES,
+        tests: objects.len() * LANES,
+        secondary: 0,
+    });
+
+    let [ox, oy, oz] = &packet.origin;
+    let [dx, dy, dz] = &packet.direction;
+
+    let mut a = [0.0; LANES];
+    for l in 0..LANES {
+        a[l] = dx[l] * dx[l] + dy[l] * dy[l] + dz[l] * dz[l];
+    }
+
+    let mut closest_t = [t_max; LANES];
+    let mut closest: [Option<usize>; LANES] = [None; LANES];
+    // Hits from the scalar fallback. Sphere hits are only filled in at the
+    // end, for the lanes a sphere ends up winning.
+    let mut hits: [Option<Hit>; LANES] = [None; LANES];
+
+    for (i, object) in objects.iter().enumerate() {
+        let Object::Sphere(sphere) = object else {
+            for l in 0..LANES {
+                let (origin, direction) = packet.ray(l);
+                if let Some(hit) = object.intersect(origin, direction, t_min, closest_t[l]) {
+                    closest_t[l] = hit.t;
+                    closest[l] = Some(i);
+                    hits[l] = Some(hit);
+                }
+            }
+            continue;
+        };
+
+        let center = sphere.center;
+        let r2 = sphere.radius * sphere.radius;
+
+        let mut t = [0.0; LANES];
+        let mut hit = [false; LANES];
+        for l in 0..LANES {
+            let (cx, cy, cz) = (ox[l] - center.x, oy[l] - center.y, oz[l] - center.z);
+            let b = 2.0 * (cx * dx[l] + cy * dy[l] + cz * dz[l]);
+            let c = cx * cx + cy * cy + cz * cz - r2;
+
+            let discriminant = b * b - 4.0 * a[l] * c;
+            let root = discriminant.max(0.0).sqrt();
+            let near = (-b - root) / (2.0 * a[l]);
+            let far = (-b + root) / (2.0 * a[l]);
+
+            t[l] = if near > t_min { near } else { far };
+            hit[l] = discriminant >= 0.0 && t_min < t[l] && t[l] < closest_t[l];
+        }
+
+        for l in 0..LANES {
+            if hit[l] {
+                closest_t[l] = t[l];
+                closest[l] = Some(i);
+                hits[l] = None;
+            }
+        }
+    }
+
+    std::array::from_fn(|l| {
+        let i = closest[l]?;
+        let hit = hits[l].or_else(|| {
+            let Object::Sphere(sphere) = &objects[i] else {
+                return None;
+            };
+            let (origin, direction) = packet.ray(l);
+            let t = closest_t[l];
+            Some(sphere.hit(t, origin + t * direction))
+        })?;
+        Some((i, hit))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{CsgOperation, Heightmap, Sdf, TextureImage};
+    use notan::math::Mat3;
+    use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4};
+
+    fn triangle(vertex1: Vec3, vertex2: Vec3, vertex3: Vec3) -> Triangle {
+        Triangle {
+            vertex1,
+            vertex2,
+            vertex3,
+            normals: None,
+            texture: None,
+            emission: 0.0,
+        }
+    }
+
+    // A right triangle in the z = 5 plane, facing the origin.
+    fn facing() -> Triangle {
+        triangle(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 2.0, 5.0),
+            Vec3::new(2.0, 0.0, 5.0),
+        )
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{a} != {b}");
+    }
+
+    #[test]
+    fn hit_gives_distance_and_barycentrics() {
+        let (t, u, v) =
+            ray_intersects_triangle(Vec3::new(0.5, 0.5, 0.0), Vec3::Z, &facing()).unwrap();
+
+        assert_close(t, 5.0);
+        assert_close(u, 0.25);
+        assert_close(v, 0.25);
+    }
+
+    #[test]
+    fn distance_counts_direction_lengths() {
+        let (t, _, _) =
+            ray_intersects_triangle(Vec3::new(0.5, 0.5, 0.0), Vec3::Z * 2.0, &facing()).unwrap();
+
+        assert_close(t, 2.5);
+    }
+
+    #[test]
+    fn miss_outside_edges() {
+        for origin in [
+            Vec3::new(-0.1, 0.5, 0.0),
+            Vec3::new(0.5, -0.1, 0.0),
+            Vec3::new(1.1, 1.1, 0.0),
+        ] {
+            assert!(ray_intersects_triangle(origin, Vec3::Z, &facing()).is_none());
+        }
+    }
+
+    // Projecting onto the edges instead of solving for the barycentrics gets
+    // both of these wrong when the edges aren't perpendicular.
+    #[test]
+    fn skewed_triangle() {
+        let skewed = triangle(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(4.0, 0.0, 5.0),
+            Vec3::new(3.0, 1.0, 5.0),
+        );
+
+        let (_, u, v) =
+            ray_intersects_triangle(Vec3::new(3.0, 0.9, 0.0), Vec3::Z, &skewed).unwrap();
+        assert_close(u, 0.075);
+        assert_close(v, 0.9);
+
+        assert!(ray_intersects_triangle(Vec3::new(3.5, 0.9, 0.0), Vec3::Z, &skewed).is_none());
+    }
+
+    #[test]
+    fn grazing_hits_on_edges_and_vertices() {
+        let (_, u, _) =
+            ray_intersects_triangle(Vec3::new(0.0, 1.0, 0.0), Vec3::Z, &facing()).unwrap();
+        assert_close(u, 0.5);
+
+        let (_, u, v) =
+            ray_intersects_triangle(Vec3::new(1.0, 1.0, 0.0), Vec3::Z, &facing()).unwrap();
+        assert_close(u + v, 1.0);
+
+        let (_, u, v) =
+            ray_intersects_triangle(Vec3::new(0.0, 0.0, 0.0), Vec3::Z, &facing()).unwrap();
+        assert_close(u, 0.0);
+        assert_close(v, 0.0);
+    }
+
+    #[test]
+    fn ray_in_the_plane_misses() {
+        let origin = Vec3::new(-1.0, 0.5, 5.0);
+        assert!(ray_intersects_triangle(origin, Vec3::X, &facing()).is_none());
+    }
+
+    #[test]
+    fn backfaces_are_hit() {
+        let (t, u, v) =
+            ray_intersects_triangle(Vec3::new(0.5, 0.5, 10.0), -Vec3::Z, &facing()).unwrap();
+
+        assert_close(t, 5.0);
+        assert_close(u, 0.25);
+        assert_close(v, 0.25);
+    }
+
+    #[test]
+    fn behind_the_origin_misses() {
+        let origin = Vec3::new(0.5, 0.5, 6.0);
+        assert!(ray_intersects_triangle(origin, Vec3::Z, &facing()).is_none());
+    }
+
+    #[test]
+    fn degenerate_triangles_miss() {
+        let line = triangle(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(1.0, 1.0, 5.0),
+            Vec3::new(2.0, 2.0, 5.0),
+        );
+        let point = triangle(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 5.0),
+        );
+
+        assert!(ray_intersects_triangle(Vec3::new(1.0, 1.0, 0.0), Vec3::Z, &line).is_none());
+        assert!(ray_intersects_triangle(Vec3::ZERO, Vec3::Z, &point).is_none());
+    }
+
+    #[test]
+    fn closest_hit_picks_the_nearer_triangle() {
+        let far = triangle(
+            Vec3::new(-5.0, -5.0, 8.0),
+            Vec3::new(-5.0, 15.0, 8.0),
+            Vec3::new(15.0, -5.0, 8.0),
+        );
+        let objects = [Object::Triangle(far), Object::Triangle(facing())];
+
+        // Moving the camera changes which one is in front, so the hit
+        // distance has to be measured from the ray origin.
+        let (i, hit) = closest_hit(
+            Vec3::new(0.5, 0.5, 0.0),
+            Vec3::Z,
+            0.0,
+            f32::INFINITY,
+            &objects,
+        )
+        .unwrap();
+        assert_eq!(i, 1);
+        assert_close(hit.t, 5.0);
+
+        let (i, hit) = closest_hit(
+            Vec3::new(0.5, 0.5, 6.0),
+            Vec3::Z,
+            0.0,
+            f32::INFINITY,
+            &objects,
+        )
+        .unwrap();
+        assert_eq!(i, 0);
+        assert_close(hit.t, 2.0);
+    }
+
+    #[test]
+    fn counting_keeps_each_call_to_itself() {
+        let objects = [Object::Triangle(facing())];
+        let trace = || closest_hit(Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY, &objects);
+
+        let (_, outer) = counting(|| {
+            trace();
+            let (_, inner) = counting(|| {
+                trace();
+                trace();
+            });
+            assert_eq!((inner.rays, inner.tests), (2, 2));
+        });
+        assert_eq!((outer.rays, outer.tests), (1, 1));
+    }
+
+    #[test]
+    fn intersect_respects_the_ray_interval() {
+        let triangle = facing();
+        let origin = Vec3::new(0.5, 0.5, 0.0);
+
+        assert!(triangle.intersect(origin, Vec3::Z, 0.0, 4.0).is_none());
+        assert!(triangle.intersect(origin, Vec3::Z, 6.0, 10.0).is_none());
+
+        let hit = triangle.intersect(origin, Vec3::Z, 0.0, 10.0).unwrap();
+        assert_close(hit.t, 5.0);
+        assert_close(hit.normal.z.abs(), 1.0);
+    }
+
+    #[test]
+    fn vertex_normals_blend_across_the_face() {
+        let smooth = Triangle {
+            normals: Some([Vec3::X, Vec3::Y, Vec3::Z]),
+            ..facing()
+        };
+
+        let hit = smooth.intersect(Vec3::ZERO, Vec3::Z, 0.0, 10.0).unwrap();
+        assert!(hit.normal.abs_diff_eq(Vec3::X, 1e-5));
+
+        let hit = smooth
+            .intersect(Vec3::new(0.5, 0.5, 0.0), Vec3::Z, 0.0, 10.0)
+            .unwrap();
+        let blend = Vec3::new(0.5, 0.25, 0.25).normalize();
+        assert!(hit.normal.abs_diff_eq(blend, 1e-5));
+    }
+
+    fn sphere(center: Vec3, radius: f32) -> Sphere {
+        Sphere {
+            center,
+            radius,
+            texture: None,
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn sphere_gives_both_crossings() {
+        let (far, near) =
+            ray_intersects_sphere(Vec3::ZERO, Vec3::Z, &sphere(Vec3::new(0.0, 0.0, 5.0), 1.0));
+
+        assert_close(near, 4.0);
+        assert_close(far, 6.0);
+    }
+
+    #[test]
+    fn sphere_distances_count_direction_lengths() {
+        let (far, near) = ray_intersects_sphere(
+            Vec3::ZERO,
+            Vec3::Z * 2.0,
+            &sphere(Vec3::new(0.0, 0.0, 5.0), 1.0),
+        );
+
+        assert_close(near, 2.0);
+        assert_close(far, 3.0);
+    }
+
+    #[test]
+    fn sphere_miss_is_infinitely_far() {
+        let (far, near) =
+            ray_intersects_sphere(Vec3::ZERO, Vec3::Z, &sphere(Vec3::new(0.0, 3.0, 5.0), 1.0));
+
+        assert_eq!(near, f32::INFINITY);
+        assert_eq!(far, f32::INFINITY);
+    }
+
+    #[test]
+    fn sphere_around_the_origin_is_entered_behind_it() {
+        let (far, near) = ray_intersects_sphere(Vec3::ZERO, Vec3::Z, &sphere(Vec3::ZERO, 2.0));
+
+        assert_close(near, -2.0);
+        assert_close(far, 2.0);
+    }
+
+    #[test]
+    fn sphere_grazed_at_one_point() {
+        let (far, near) =
+            ray_intersects_sphere(Vec3::ZERO, Vec3::Z, &sphere(Vec3::new(1.0, 0.0, 5.0), 1.0));
+
+        assert_close(near, 5.0);
+        assert_close(far, 5.0);
+    }
+
+    #[test]
+    fn sphere_hit_respects_the_ray_interval() {
+        let sphere = sphere(Vec3::new(0.0, 0.0, 5.0), 1.0);
+
+        let hit = sphere.intersect(Vec3::ZERO, Vec3::Z, 0.0, 10.0).unwrap();
+        assert_close(hit.t, 4.0);
+        assert_close(hit.normal.z, -1.0);
+
+        // Starting past the near side, the far side is the one seen.
+        let hit = sphere.intersect(Vec3::ZERO, Vec3::Z, 4.5, 10.0).unwrap();
+        assert_close(hit.t, 6.0);
+
+        assert!(sphere.intersect(Vec3::ZERO, Vec3::Z, 0.0, 3.0).is_none());
+    }
+
+    fn unit_box(center: Vec3) -> Cuboid {
+        Cuboid {
+            center,
+            half_extents: Vec3::splat(1.0),
+            rotation: Mat3::IDENTITY,
+            texture: None,
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn cuboid_hit_gives_the_face_normal() {
+        let (t, normal) =
+            ray_intersects_cuboid(Vec3::ZERO, Vec3::Z, &unit_box(Vec3::new(0.0, 0.0, 5.0)))
+                .unwrap();
+
+        assert_close(t, 4.0);
+        assert_eq!(normal, -Vec3::Z);
+    }
+
+    #[test]
+    fn cuboid_from_inside_hits_the_exit() {
+        let (t, normal) =
+            ray_intersects_cuboid(Vec3::ZERO, Vec3::Y, &unit_box(Vec3::ZERO)).unwrap();
+
+        assert_close(t, 1.0);
+        assert_eq!(normal, Vec3::Y);
+    }
+
+    #[test]
+    fn cuboid_behind_the_origin_misses() {
+        let cuboid = unit_box(Vec3::new(0.0, 0.0, -5.0));
+        assert!(ray_intersects_cuboid(Vec3::ZERO, Vec3::Z, &cuboid).is_none());
+    }
+
+    // Axis aligned rays divide by zero in the slab test for the other two
+    // axes. The infinities that gives have to keep rays inside those slabs
+    // hitting and rays outside them missing.
+    #[test]
+    fn cuboid_with_zero_direction_components() {
+        let cuboid = unit_box(Vec3::new(0.0, 0.0, 5.0));
+
+        let (t, normal) =
+            ray_intersects_cuboid(Vec3::new(0.5, -0.5, 0.0), Vec3::Z, &cuboid).unwrap();
+        assert_close(t, 4.0);
+        assert_eq!(normal, -Vec3::Z);
+
+        assert!(ray_intersects_cuboid(Vec3::new(1.5, 0.0, 0.0), Vec3::Z, &cuboid).is_none());
+        assert!(ray_intersects_cuboid(Vec3::new(0.0, -1.5, 0.0), Vec3::Z, &cuboid).is_none());
+
+        // Sideways through the box, with only x nonzero.
+        let (t, _) = ray_intersects_cuboid(Vec3::new(-5.0, 0.0, 5.0), Vec3::X, &cuboid).unwrap();
+        assert_close(t, 4.0);
+    }
+
+    // The worst case: starting exactly on a slab's boundary plane, where the
+    // slab test computes 0 * infinity. Whether it counts as a hit is a coin
+    // toss, but a hit mustn't come back with a NaN distance.
+    #[test]
+    fn cuboid_ray_along_a_face() {
+        let cuboid = unit_box(Vec3::new(0.0, 0.0, 5.0));
+
+        for origin in [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0)] {
+            if let Some((t, normal)) = ray_intersects_cuboid(origin, Vec3::Z, &cuboid) {
+                assert!(t.is_finite(), "{t}");
+                assert!(normal.is_finite(), "{normal}");
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_cuboid_normal_is_in_world_space() {
+        let cuboid = Cuboid {
+            rotation: Mat3::from_rotation_y(FRAC_PI_4),
+            ..unit_box(Vec3::new(0.0, 0.0, 5.0))
+        };
+
+        // Just off the edge facing the ray, so only one face is hit.
+        let (t, normal) =
+            ray_intersects_cuboid(Vec3::new(0.3, 0.0, 0.0), Vec3::Z, &cuboid).unwrap();
+        assert_close(t, 5.0 - 2.0f32.sqrt() + 0.3);
+        assert_close(normal.x, FRAC_1_SQRT_2);
+        assert_close(normal.y, 0.0);
+        assert_close(normal.z, -FRAC_1_SQRT_2);
+    }
+
+    fn assert_near(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() < tolerance, "{a} != {b}");
+    }
+
+    // Upright, from y = -1 to 1 around (0, 0, 5).
+    fn upright_cylinder() -> Cylinder {
+        Cylinder {
+            start: Vec3::new(0.0, -1.0, 5.0),
+            end: Vec3::new(0.0, 1.0, 5.0),
+            radius: 1.0,
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn cylinder_hit_on_the_side() {
+        let hit = upright_cylinder()
+            .intersect(Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+
+        assert_close(hit.t, 4.0);
+        assert!(hit.normal.normalize().abs_diff_eq(-Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn cylinder_passed_over_misses() {
+        let cylinder = upright_cylinder();
+        assert!(ray_intersects_cylinder(Vec3::new(0.0, 1.5, 0.0), Vec3::Z, &cylinder).is_none());
+    }
+
+    #[test]
+    fn cylinder_from_inside_hits_the_far_side() {
+        let cylinder = upright_cylinder();
+        let (entry, exit) =
+            ray_intersects_cylinder(Vec3::new(0.0, 0.0, 5.0), Vec3::Z, &cylinder).unwrap();
+        assert_close(entry, -1.0);
+        assert_close(exit, 1.0);
+
+        let hit = cylinder
+            .intersect(Vec3::new(0.0, 0.0, 5.0), Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 1.0);
+    }
+
+    // Along the axis the tube's quadratic has nothing to solve, and only the
+    // caps bound the span.
+    #[test]
+    fn cylinder_ray_along_the_axis_hits_a_cap() {
+        let cylinder = upright_cylinder();
+
+        let hit = cylinder
+            .intersect(Vec3::new(0.5, -5.0, 5.0), Vec3::Y, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 4.0);
+        assert_eq!(hit.normal, -Vec3::Y);
+
+        assert!(ray_intersects_cylinder(Vec3::new(1.5, -5.0, 5.0), Vec3::Y, &cylinder).is_none());
+    }
+
+    fn upright_capsule() -> Capsule {
+        Capsule {
+            start: Vec3::new(0.0, -1.0, 5.0),
+            end: Vec3::new(0.0, 1.0, 5.0),
+            radius: 0.5,
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn capsule_hit_on_the_side() {
+        let hit = upright_capsule()
+            .intersect(Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+
+        assert_close(hit.t, 4.5);
+        assert!(hit.normal.normalize().abs_diff_eq(-Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn capsule_passed_over_misses() {
+        let capsule = upright_capsule();
+        assert!(ray_intersects_capsule(Vec3::new(0.0, 1.6, 0.0), Vec3::Z, &capsule).is_none());
+    }
+
+    #[test]
+    fn capsule_from_inside_hits_the_far_side() {
+        let hit = upright_capsule()
+            .intersect(Vec3::new(0.0, 0.0, 5.0), Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 0.5);
+    }
+
+    // Along the axis the ray meets the rounded end, not a flat cap.
+    #[test]
+    fn capsule_ray_along_the_axis_hits_an_end() {
+        let hit = upright_capsule()
+            .intersect(Vec3::new(0.0, -5.0, 5.0), Vec3::Y, 0.0, f32::INFINITY)
+            .unwrap();
+
+        assert_close(hit.t, 3.5);
+        assert!(hit.normal.normalize().abs_diff_eq(-Vec3::Y, 1e-5));
+    }
+
+    // Lying flat around (0, 0, 5).
+    fn flat_torus() -> Torus {
+        Torus {
+            center: Vec3::new(0.0, 0.0, 5.0),
+            major_radius: 1.0,
+            minor_radius: 0.25,
+            rotation: Mat3::IDENTITY,
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn torus_crossed_through_the_middle_gives_four_roots() {
+        let roots = ray_intersects_torus(Vec3::ZERO, Vec3::Z, &flat_torus());
+
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.into_iter().zip([3.75, 4.25, 5.75, 6.25]) {
+            assert_near(root, expected, 1e-4);
+        }
+
+        let hit = flat_torus()
+            .intersect(Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_near(hit.t, 3.75, 1e-4);
+        assert!(hit.normal.normalize().abs_diff_eq(-Vec3::Z, 1e-3));
+    }
+
+    #[test]
+    fn torus_misses_above_and_through_the_hole() {
+        let torus = flat_torus();
+
+        assert!(ray_intersects_torus(Vec3::new(0.0, 2.0, 0.0), Vec3::Z, &torus).is_empty());
+        assert!(ray_intersects_torus(Vec3::new(0.0, 5.0, 5.0), -Vec3::Y, &torus).is_empty());
+    }
+
+    #[test]
+    fn torus_from_inside_the_tube_hits_its_wall() {
+        let hit = flat_torus()
+            .intersect(Vec3::new(1.0, 0.0, 5.0), Vec3::Y, 0.0, f32::INFINITY)
+            .unwrap();
+
+        assert_near(hit.t, 0.25, 1e-4);
+        assert!(hit.normal.normalize().abs_diff_eq(Vec3::Y, 1e-3));
+    }
+
+    // Straight down onto the top of the tube, with a direction that has only
+    // one nonzero component.
+    #[test]
+    fn torus_ray_straight_down_hits_the_top() {
+        let hit = flat_torus()
+            .intersect(Vec3::new(1.0, 5.0, 5.0), -Vec3::Y, 0.0, f32::INFINITY)
+            .unwrap();
+
+        assert_near(hit.t, 4.75, 1e-4);
+        assert!(hit.normal.normalize().abs_diff_eq(Vec3::Y, 1e-3));
+    }
+
+    fn sdf_ball(center: Vec3) -> SdfObject {
+        SdfObject {
+            sdf: Sdf::Sphere {
+                center: Vec3::ZERO,
+                radius: 1.0,
+            },
+            position: center,
+            scale: 1.0,
+            rotation: Mat3::IDENTITY,
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn sdf_march_reaches_the_surface() {
+        let ball = sdf_ball(Vec3::new(0.0, 0.0, 5.0));
+
+        let hit = ball
+            .intersect(Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_near(hit.t, 4.0, MARCH_EPSILON);
+        assert!(hit.normal.normalize().abs_diff_eq(-Vec3::Z, 1e-3));
+
+        // Distances are along the ray as given, not the normalised one.
+        let hit = ball
+            .intersect(Vec3::ZERO, Vec3::Z * 2.0, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_near(hit.t, 2.0, MARCH_EPSILON);
+    }
+
+    #[test]
+    fn sdf_march_misses_past_the_side() {
+        let ball = sdf_ball(Vec3::new(0.0, 0.0, 5.0));
+        assert!(ball
+            .intersect(Vec3::new(0.0, 1.5, 0.0), Vec3::Z, 0.0, f32::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn sdf_spans_from_inside_start_behind_the_origin() {
+        let ball = sdf_ball(Vec3::new(0.0, 0.0, 5.0));
+        let spans = ball.spans(Vec3::new(0.0, 0.0, 5.0), Vec3::X);
+
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].entry.t, f32::NEG_INFINITY);
+        assert_near(spans[0].exit.t, 1.0, MARCH_EPSILON);
+    }
+
+    // Just past the side the distance never gets below the epsilon, so the
+    // march runs out of steps or distance rather than hitting.
+    #[test]
+    fn sdf_march_grazing_the_side_gives_up() {
+        let ball = sdf_ball(Vec3::new(0.0, 0.0, 5.0));
+        assert!(ball
+            .intersect(Vec3::new(0.0, 1.01, 0.0), Vec3::Z, 0.0, f32::INFINITY)
+            .is_none());
+    }
+
+    fn span(entry: f32, exit: f32) -> Span {
+        Span {
+            entry: surface(entry, -Vec3::Z),
+            exit: surface(exit, Vec3::Z),
+        }
+    }
+
+    fn ts(spans: &[Span]) -> Vec<(f32, f32)> {
+        spans.iter().map(|s| (s.entry.t, s.exit.t)).collect()
+    }
+
+    #[test]
+    fn combined_spans_follow_the_operation() {
+        let a = [span(1.0, 4.0)];
+        let b = [span(3.0, 6.0), span(8.0, 9.0)];
+
+        let union = combine_spans(&a, &b, |a, b| a || b, false);
+        assert_eq!(ts(&union), [(1.0, 6.0), (8.0, 9.0)]);
+
+        let intersection = combine_spans(&a, &b, |a, b| a && b, false);
+        assert_eq!(ts(&intersection), [(3.0, 4.0)]);
+
+        let difference = combine_spans(&a, &b, |a, b| a && !b, true);
+        assert_eq!(ts(&difference), [(1.0, 3.0)]);
+        // The carved face is b's entry, turned to face out of what's left.
+        assert_eq!(difference[0].exit.normal, Vec3::Z);
+
+        assert!(combine_spans(&a, &[], |a, b| a && b, false).is_empty());
+    }
+
+    // A unit ball at (0, 0, 5) with a dent taken out of the side facing the
+    // origin.
+    fn dented_ball() -> Csg {
+        Csg {
+            operation: CsgOperation::Difference,
+            a: Box::new(Object::Sphere(sphere(Vec3::ZERO, 1.0))),
+            b: Box::new(Object::Sphere(sphere(Vec3::new(0.0, 0.0, -1.0), 0.5))),
+            position: Vec3::new(0.0, 0.0, 5.0),
+            scale: 1.0,
+            rotation: Mat3::IDENTITY,
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn csg_difference_hits_the_carved_surface() {
+        let hit = dented_ball()
+            .intersect(Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+
+        assert_close(hit.t, 4.5);
+        assert!(hit.normal.z < 0.0);
+    }
+
+    #[test]
+    fn csg_misses_and_exits_from_inside() {
+        let ball = dented_ball();
+        assert!(ball
+            .intersect(Vec3::new(0.0, 1.5, 0.0), Vec3::Z, 0.0, f32::INFINITY)
+            .is_none());
+
+        let hit = ball
+            .intersect(Vec3::new(0.0, 0.0, 5.0), Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 1.0);
+    }
+
+    // Sideways past the dent, only the ball is in the way.
+    #[test]
+    fn csg_ray_along_an_axis_clear_of_the_dent() {
+        let hit = dented_ball()
+            .intersect(Vec3::new(-5.0, 0.0, 5.0), Vec3::X, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 4.0);
+    }
+
+    // Two units across around (0, 0, 5), rising from y = 0 at x = -1 to
+    // y = 2 at x = 1, over a three by three grid.
+    fn ramp() -> Terrain {
+        Terrain {
+            center: Vec3::new(0.0, 0.0, 5.0),
+            size: 2.0,
+            height: 2.0,
+            heightmap: Heightmap::Image(TextureImage {
+                path: String::new(),
+                width: 3,
+                height: 3,
+                pixels: [0.0, 0.5, 1.0].repeat(3).into(),
+            }),
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn terrain_hit_from_above() {
+        let hit = ramp()
+            .intersect(Vec3::new(0.5, 5.0, 5.0), -Vec3::Y, 0.0, f32::INFINITY)
+            .unwrap();
+
+        assert_close(hit.t, 3.5);
+        assert!(hit
+            .normal
+            .abs_diff_eq(Vec3::new(-FRAC_1_SQRT_2, FRAC_1_SQRT_2, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn terrain_misses_outside_its_edges() {
+        let terrain = ramp();
+        assert!(terrain
+            .intersect(Vec3::new(1.5, 5.0, 5.0), -Vec3::Y, 0.0, f32::INFINITY)
+            .is_none());
+        assert!(terrain
+            .intersect(Vec3::new(-5.0, 2.5, 5.0), Vec3::X, 0.0, f32::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn terrain_hit_from_inside_its_bounds() {
+        let hit = ramp()
+            .intersect(Vec3::new(0.5, 1.9, 5.0), -Vec3::Y, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 0.4);
+    }
+
+    // Level across the grid, with no z component, the walk steps from cell
+    // to cell in x only until the ramp rises to meet the ray.
+    #[test]
+    fn terrain_walk_along_an_axis() {
+        let hit = ramp()
+            .intersect(Vec3::new(-3.0, 1.5, 5.0), Vec3::X, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 3.5);
+
+        // A diagonal from the far corner crosses cells in both directions.
+        let direction = Vec3::new(-1.0, 0.0, -1.0);
+        let hit = ramp()
+            .intersect(Vec3::new(2.0, 0.5, 7.0), direction, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 2.5);
+    }
+
+    // A slab to the mesh, turned a quarter turn and doubled by the instance,
+    // so its long side ends up along z.
+    fn slab_instance() -> Instance {
+        let slab = Object::Cuboid(Cuboid {
+            half_extents: Vec3::new(1.0, 0.5, 0.25),
+            ..unit_box(Vec3::ZERO)
+        });
+        Instance {
+            mesh: String::from("slab"),
+            objects: vec![slab].into(),
+            position: Vec3::new(0.0, 0.0, 5.0),
+            scale: 2.0,
+            rotation: Mat3::from_rotation_y(FRAC_PI_2),
+            emission: 0.0,
+        }
+    }
+
+    #[test]
+    fn instance_hit_is_in_world_space() {
+        let hit = slab_instance()
+            .intersect(Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+
+        assert_close(hit.t, 3.0);
+        assert!(hit.normal.abs_diff_eq(-Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn instance_misses_past_its_scaled_edge() {
+        assert!(slab_instance()
+            .intersect(Vec3::new(0.0, 1.1, 0.0), Vec3::Z, 0.0, f32::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn instance_from_inside_hits_the_far_side() {
+        let instance = slab_instance();
+        let hit = instance
+            .intersect(Vec3::new(0.0, 0.0, 5.0), Vec3::Z, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_close(hit.t, 2.0);
+
+        let spans = instance.spans(Vec3::new(0.0, 0.0, 5.0), Vec3::X);
+        assert_eq!(spans.len(), 1);
+        assert_close(spans[0].entry.t, -0.5);
+        assert_close(spans[0].exit.t, 0.5);
+    }
+}
@@ -0,0 +1,20 @@
+// The raytracer behind cast. Scenes are loaded and animated in `scene`, rays
+// are intersected with them in `geometry` and shaded in `lighting`, and
+// `render` turns a camera's view into a grid of characters or an image.
+// `control` flies the camera, edits the scene and keeps the frame rate up
+// from whatever input the front end passes it. `generate` builds large scenes
+// for stress testing, `noise` the heights of terrain, and `asciicast` records
+// character frames for terminal players. Nothing here depends on a window,
+// so it can be driven without one.
+
+pub mod asciicast;
+pub mod camera;
+pub mod control;
+pub mod generate;
+pub mod geometry;
+pub mod lighting;
+pub mod noise;
+mod parallel;
+pub mod render;
+pub mod scene;
+pub mod settings;
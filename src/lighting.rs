@@ -0,0 +1,367 @@
+use crate::geometry::{closest_hit, count, RayCounts};
+use crate::scene::{Background, Object};
+use crate::settings::Settings;
+use notan::math::Vec3;
+use std::f32::consts::{PI, TAU};
+
+// Shading weights for the ambient term and the point light at the camera.
+// Both lights are white, so surfaces take on the colour of their albedo.
+#[derive(Clone)]
+pub struct Lighting {
+    pub ambient: f32,
+    pub diffuse: f32,
+}
+
+pub fn compute_lighting(
+    p: Vec3,
+    n: Vec3,
+    albedo: Vec3,
+    player_pos: Vec3,
+    lighting: &Lighting,
+) -> Vec3 {
+    let mut i = Vec3::splat(lighting.ambient);
+
+    // let light_pos = Vec3 {
+    //     x: 2.0,
+    //     y: 1.0,
+    //     z: -3.0,
+    // };
+    let light_pos = player_pos;
+
+    let l = light_pos - p;
+
+    let n_dot_l = n.dot(l);
+    if n_dot_l > 0.0 {
+        i += lighting.diffuse * albedo * n_dot_l / (n.length() * l.length());
+    }
+
+    i
+}
+
+// The cone of directions from `point` that covers a light's bounding sphere,
+// as its unit axis and the cosine of its half angle. None from inside the
+// sphere, where the light can't be told apart from the surface.
+pub fn light_cone(point: Vec3, center: Vec3, radius: f32) -> Option<(Vec3, f32)> {
+    let to_center = center - point;
+    let distance_squared = to_center.length_squared();
+    if distance_squared <= radius * radius {
+        return None;
+    }
+    let cos_max = (1.0 - radius * radius / distance_squared).sqrt();
+    Some((to_center / distance_squared.sqrt(), cos_max))
+}
+
+pub fn cone_solid_angle(cos_max: f32) -> f32 {
+    TAU * (1.0 - cos_max)
+}
+
+// Objects that give off light and have bounds to aim at. Emissive planes,
+// SDFs and CSG nodes light the scene only when bounced rays happen to find
+// them.
+fn area_lights(objects: &[Object]) -> impl Iterator<Item = (usize, f32, Vec3, f32)> + '_ {
+    objects.iter().enumerate().filter_map(|(i, object)| {
+        let emission = object.emission();
+        let (center, radius) = object.bounds()?;
+        (emission > 0.0).then_some((i, emission, center, radius))
+    })
+}
+
+// Light reaching `point` from every emissive object but the one it lies on,
+// without shadows, for the fast view. Each light is treated as a small disc
+// facing the point, and gives off white light.
+pub fn area_lighting(point: Vec3, normal: Vec3, exclude: usize, objects: &[Object]) -> Vec3 {
+    area_lights(objects)
+        .filter(|(i, ..)| *i != exclude)
+        .filter_map(|(_, emission, center, radius)| {
+            let (axis, cos_max) = light_cone(point, center, radius)?;
+            let n_dot_l = normal.dot(axis).max(0.0);
+            Some(Vec3::splat(
+                emission * n_dot_l * cone_solid_angle(cos_max) / PI,
+            ))
+        })
+        .sum()
+}
+
+// One shadow ray towards a random point on a random light, weighted so that
+// on average it gives all the direct light from emissive objects. Rays that
+// reach the light from different points across it are what soften the
+// shadows' edges.
+pub fn sample_area_light(
+    point: Vec3,
+    normal: Vec3,
+    exclude: usize,
+    objects: &[Object],
+    lights: usize,
+    rng: &mut Rng,
+) -> Vec3 {
+    if lights == 0 {
+        return Vec3::ZERO;
+    }
+    let pick = ((rng.next_f32() * lights as f32) as usize).min(lights - 1);
+    let Some((index, emission, center, radius)) = area_lights(objects).nth(pick) else {
+        return Vec3::ZERO;
+    };
+    if index == exclude {
+        return Vec3::ZERO;
+    }
+    let Some((axis, cos_max)) = light_cone(point, center, radius) else {
+        return Vec3::ZERO;
+    };
+
+    // Uniform over the cone, which always contains the whole light.
+    let cos_theta = 1.0 - rng.next_f32() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = TAU * rng.next_f32();
+    let tangent = axis.any_orthonormal_vector();
+    let bitangent = axis.cross(tangent);
+    let direction = (tangent * phi.cos() + bitangent * phi.sin()) * sin_theta + axis * cos_theta;
+
+    let n_dot_l = normal.dot(direction);
+    if n_dot_l <= 0.0 {
+        return Vec3::ZERO;
+    }
+    match closest_hit(point, direction, BOUNCE_EPSILON, f32::INFINITY, objects) {
+        Some((i, _)) if i == index => {
+            Vec3::splat(emission * n_dot_l * cone_solid_angle(cos_max) / PI * lights as f32)
+        }
+        _ => Vec3::ZERO,
+    }
+}
+
+// Path tracing: the default bounces per path, how many frames are accumulated
+// before a still image is considered converged, and the light arriving from
+// the sky.
+pub const MAX_BOUNCES: usize = 4;
+pub const MAX_PASSES: u32 = 256;
+pub const SKY_RADIANCE: f32 = 0.4;
+pub const BOUNCE_EPSILON: f32 = 1e-3;
+
+// Russian roulette: from this many bounces on a path only carries on with a
+// chance that follows its throughput, never below MIN_SURVIVAL, and is
+// weighted up when it does to make up for the paths that stopped. The paths
+// cut most often are the ones that had the least left to add.
+pub const ROULETTE_BOUNCES: usize = 2;
+pub const MIN_SURVIVAL: f32 = 0.05;
+
+// Takes a ray out of what's left of a path's budget, if there's any left.
+fn spend(budget: &mut Option<usize>) -> bool {
+    match budget {
+        Some(0) => return false,
+        Some(rays) => *rays -= 1,
+        None => {}
+    }
+    count(RayCounts {
+        secondary: 1,
+        ..RayCounts::default()
+    });
+    true
+}
+
+// A small xorshift generator, seeded per cell and pass so frames are
+// reproducible.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Mix the seed so neighbouring cells don't start out correlated.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Cosine weighted direction about `normal`, the importance sample for a
+// Lambertian surface.
+pub fn sample_hemisphere(normal: Vec3, rng: &mut Rng) -> Vec3 {
+    let (r1, r2) = (rng.next_f32(), rng.next_f32());
+    let phi = TAU * r1;
+    let r = r2.sqrt();
+
+    let tangent = normal.any_orthonormal_vector();
+    let bitangent = normal.cross(tangent);
+    (tangent * phi.cos() * r + bitangent * phi.sin() * r + normal * (1.0 - r2).sqrt()).normalize()
+}
+
+// Follows one path of diffuse bounces from the camera. Each surface along
+// the way picks up light from the camera's point light and a sample of the
+// emissive objects, where it can see them, and paths that escape collect the
+// background, or light from above when the scene has none. Returns the
+// radiance and the distance along the first ray, or None if the first ray
+// misses with no background to see.
+//
+// `budget` is how many shadow and bounce rays the path may cast, if it's
+// limited. Once it runs out lights are added without shadows, as in the fast
+// view, and the path ends at the next surface.
+pub fn path_trace(
+    origin: Vec3,
+    direction: Vec3,
+    objects: &[Object],
+    background: Option<&Background>,
+    settings: &Settings,
+    rng: &mut Rng,
+    mut budget: Option<usize>,
+) -> Option<(Vec3, f32)> {
+    let light = origin;
+    let (mut origin, mut direction) = (origin, direction);
+    let (mut radiance, mut throughput) = (Vec3::ZERO, Vec3::ONE);
+    let mut depth = f32::INFINITY;
+    let lights = area_lights(objects).count();
+
+    for bounce in 0..settings.max_bounces {
+        if bounce > 0 && !spend(&mut budget) {
+            break;
+        }
+        let t_min = if bounce == 0 { 1.0 } else { BOUNCE_EPSILON };
+        let Some((index, hit)) = closest_hit(origin, direction, t_min, f32::INFINITY, objects)
+        else {
+            let sky = match background {
+                Some(background) => background.sample(direction),
+                None if bounce == 0 => return None,
+                None => Vec3::splat(SKY_RADIANCE * direction.normalize().y.max(0.0)),
+            };
+            radiance += throughput * sky;
+            break;
+        };
+
+        if bounce == 0 {
+            depth = hit.t;
+        }
+
+        // Lights with bounds were already sampled from the surface before, so
+        // finding them again by bouncing would count them twice.
+        let object = &objects[index];
+        if bounce == 0 || object.bounds().is_none() {
+            radiance += throughput * object.emission();
+        }
+
+        let point = origin + hit.t * direction;
+        let mut normal = hit.normal.normalize();
+        if normal.dot(direction) > 0.0 {
+            normal = -normal;
+        }
+        let point = point + normal * BOUNCE_EPSILON;
+        throughput *= settings.lighting.diffuse * hit.albedo;
+
+        let to_light = light - point;
+        let n_dot_l = normal.dot(to_light.normalize());
+        if n_dot_l > 0.0
+            && (!spend(&mut budget)
+                || closest_hit(
+                    point,
+                    to_light,
+                    BOUNCE_EPSILON,
+                    1.0 - BOUNCE_EPSILON,
+                    objects,
+                )
+                .is_none())
+        {
+            radiance += throughput * n_dot_l;
+        }
+        if lights > 0 {
+            radiance += throughput
+                * if spend(&mut budget) {
+                    sample_area_light(point, normal, index, objects, lights, rng)
+                } else {
+                    area_lighting(point, normal, index, objects)
+                };
+        }
+
+        if bounce + 1 >= ROULETTE_BOUNCES {
+            let survival = throughput.max_element().clamp(MIN_SURVIVAL, 1.0);
+            if rng.next_f32() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        origin = point;
+        direction = sample_hemisphere(normal, rng);
+    }
+
+    Some((radiance, depth))
+}
+
+// Exponential fog: hits fade towards the background radiance behind them
+// the further away they are. A density of zero turns it off.
+pub fn apply_fog(i: Vec3, backdrop: Vec3, distance: f32, density: f32) -> Vec3 {
+    if density <= 0.0 {
+        return i;
+    }
+    let f = (-density * distance).exp();
+    i * f + backdrop * (1.0 - f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).abs().max_element() < 1e-5, "{a} != {b}");
+    }
+
+    const LIGHTING: Lighting = Lighting {
+        ambient: 0.2,
+        diffuse: 0.5,
+    };
+
+    #[test]
+    fn surface_facing_the_light_is_lit() {
+        let i = compute_lighting(Vec3::Z, -Vec3::Z, Vec3::ONE, Vec3::ZERO, &LIGHTING);
+        assert_close(i, Vec3::splat(0.7));
+    }
+
+    #[test]
+    fn diffuse_light_follows_the_albedo() {
+        let albedo = Vec3::new(1.0, 0.5, 0.0);
+        let i = compute_lighting(Vec3::Z, -Vec3::Z, albedo, Vec3::ZERO, &LIGHTING);
+        assert_close(i, Vec3::new(0.7, 0.45, 0.2));
+    }
+
+    // Surfaces facing away from the light get the ambient term alone, never
+    // less, however far they're turned.
+    #[test]
+    fn surface_facing_away_is_only_ambient() {
+        for normal in [Vec3::Z, Vec3::X, Vec3::new(1.0, 0.0, 1.0)] {
+            let i = compute_lighting(Vec3::Z * 2.0, normal, Vec3::ONE, Vec3::ZERO, &LIGHTING);
+            assert_close(i, Vec3::splat(0.2));
+        }
+    }
+
+    #[test]
+    fn normal_length_doesnt_scale_the_light() {
+        let unit = compute_lighting(Vec3::Z, -Vec3::Z, Vec3::ONE, Vec3::ZERO, &LIGHTING);
+        let long = compute_lighting(Vec3::Z, -Vec3::Z * 3.0, Vec3::ONE, Vec3::ZERO, &LIGHTING);
+        assert_close(unit, long);
+    }
+
+    #[test]
+    fn fog_fades_towards_the_backdrop() {
+        let (i, backdrop) = (Vec3::ONE, Vec3::splat(0.25));
+
+        assert_close(apply_fog(i, backdrop, 100.0, 0.0), i);
+        assert_close(apply_fog(i, backdrop, 0.0, 0.5), i);
+        assert_close(apply_fog(i, backdrop, f32::INFINITY, 0.5), backdrop);
+
+        let halfway = apply_fog(i, backdrop, 2.0f32.ln() / 0.5, 0.5);
+        assert_close(halfway, Vec3::splat(0.625));
+    }
+
+    #[test]
+    fn spent_budget_stops_rays() {
+        let mut budget = Some(1);
+        assert!(spend(&mut budget));
+        assert!(!spend(&mut budget));
+        assert_eq!(budget, Some(0));
+
+        let mut unlimited = None;
+        assert!((0..100).all(|_| spend(&mut unlimited)));
+    }
+}
@@ -1,60 +1,223 @@
-use notan::math::Mat3;
-use notan::math::Vec3;
+mod replay;
+mod watch;
+
+use cast::asciicast::AsciiWriter;
+use cast::camera::{Camera, GRID_ASPECT};
+use cast::control::{
+    adjust_lens, adjust_resolution, apply_gamepad, constrain_movement, drop_ball, move_camera,
+    recall_bookmark, save_bookmark, spawn, Action, Edit, Gamepad, Shape, GAMEPAD_DEVICE,
+};
+use cast::generate::generate;
+use cast::geometry::closest_hit;
+use cast::render::{
+    braille_bits, image_extension, render_animation, render_image, write_image, RenderWorker,
+    BRAILLE_DOTS, CELL_HEIGHT, CELL_WIDTH, COLS, HEIGHT, ROWS, WIDTH,
+};
+use cast::scene::{
+    animate, default_scene, load_scene, remove_object, save_scene, step_scene, Scene,
+};
+use cast::settings::{
+    RenderMode, Settings, ViewLayout, DEBUG_VIEWS, MOVEMENTS, RAMPS, RESOLUTIONS, SAMPLE_COUNTS,
+    VIEW_LAYOUTS,
+};
+use notan::draw::*;
+use notan::egui::{self, EguiConfig, EguiPluginSugar};
 use notan::prelude::*;
 use notan::text::*;
-use rayon::prelude::*;
-
-const WIDTH: usize = 1920;
-const HEIGHT: usize = 1080;
-
-const ROWS: usize = HEIGHT / 16;
-const COLS: usize = WIDTH / 8;
-
-// The constant 'D' represents the distance between the camera and the projection plane.
-const D: f32 = 1.0;
-
-struct Triangle {
-    vertex1: Vec3,
-    vertex2: Vec3,
-    vertex3: Vec3,
+use notan::utils::Instant;
+use replay::{load_session, InputFrame, Recorder};
+use std::collections::HashMap;
+use std::time::Duration;
+use watch::FileWatcher;
+
+// Glyphs are rendered slightly smaller than the default text size so that each
+// one fits inside a single cell of the atlas.
+const FONT_SIZE: f32 = 13.0;
+
+// The keys that act for as long as they're held.
+const HELD_KEYS: [(KeyCode, Action); 20] = [
+    (KeyCode::W, Action::Forward),
+    (KeyCode::S, Action::Back),
+    (KeyCode::A, Action::StrafeLeft),
+    (KeyCode::D, Action::StrafeRight),
+    (KeyCode::E, Action::TurnRight),
+    (KeyCode::Q, Action::TurnLeft),
+    (KeyCode::LBracket, Action::ZoomIn),
+    (KeyCode::RBracket, Action::ZoomOut),
+    (KeyCode::Semicolon, Action::FocusNearer),
+    (KeyCode::Apostrophe, Action::FocusFurther),
+    (KeyCode::Up, Action::NudgeForward),
+    (KeyCode::Down, Action::NudgeBack),
+    (KeyCode::Left, Action::NudgeLeft),
+    (KeyCode::Right, Action::NudgeRight),
+    (KeyCode::PageUp, Action::NudgeUp),
+    (KeyCode::PageDown, Action::NudgeDown),
+    (KeyCode::Equals, Action::Grow),
+    (KeyCode::Minus, Action::Shrink),
+    (KeyCode::Period, Action::RotateRight),
+    (KeyCode::Comma, Action::RotateLeft),
+];
+
+// Number keys that add a shape in front of the camera.
+const SPAWN_KEYS: [(KeyCode, Shape); 5] = [
+    (KeyCode::Key1, Shape::Sphere),
+    (KeyCode::Key2, Shape::Cuboid),
+    (KeyCode::Key3, Shape::Cylinder),
+    (KeyCode::Key4, Shape::Capsule),
+    (KeyCode::Key5, Shape::Torus),
+];
+
+// Number keys for bookmark slots 1 to 9.
+const BOOKMARK_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+// Every character the tracer can produce is rendered once into a strip texture,
+// so a frame is just a batch of cropped quads instead of a text layout pass.
+// Braille isn't covered by the bundled font, so those glyphs are drawn as dots.
+struct GlyphAtlas {
+    texture: RenderTexture,
+    offsets: HashMap<char, (f32, f32)>,
 }
 
-struct Sphere {
-    center: Vec3,
-    radius: f32,
-}
-
-struct Viewport {
-    width: f32,
-    height: f32,
-}
+// Glyphs are laid out in rows of this many cells to keep the texture small
+// enough for every GPU.
+const ATLAS_COLUMNS: usize = 64;
+
+impl GlyphAtlas {
+    fn new(gfx: &mut Graphics, font: &Font, glyphs: &[char]) -> Self {
+        let rows = glyphs.len().div_ceil(ATLAS_COLUMNS);
+        let texture = gfx
+            .create_render_texture(
+                (ATLAS_COLUMNS * CELL_WIDTH) as u32,
+                (rows * CELL_HEIGHT) as u32,
+            )
+            .build()
+            .unwrap();
+
+        let strings: Vec<String> = glyphs.iter().map(|c| c.to_string()).collect();
+        let mut offsets = HashMap::with_capacity(glyphs.len());
+
+        let mut text = texture.create_text();
+        text.clear_options(ClearOptions::color(Color::TRANSPARENT));
+
+        let mut dots = texture.create_draw();
+
+        for (i, (glyph, string)) in glyphs.iter().zip(&strings).enumerate() {
+            let x = (i % ATLAS_COLUMNS * CELL_WIDTH) as f32;
+            let y = (i / ATLAS_COLUMNS * CELL_HEIGHT) as f32;
+            offsets.insert(*glyph, (x, y));
+
+            if let Some(bits) = braille_bits(*glyph) {
+                for (bit, (column, row)) in BRAILLE_DOTS.iter().enumerate() {
+                    if bits & (1 << bit) != 0 {
+                        dots.circle(1.2)
+                            .position(
+                                x + (*column as f32 + 0.5) * CELL_WIDTH as f32 / 2.0,
+                                y + (*row as f32 + 0.5) * CELL_HEIGHT as f32 / 4.0,
+                            )
+                            .color(Color::WHITE);
+                    }
+                }
+                continue;
+            }
+
+            text.add(string)
+                .font(font)
+                .size(FONT_SIZE)
+                .position(x + CELL_WIDTH as f32 / 2.0, y + CELL_HEIGHT as f32 / 2.0)
+                .h_align_center()
+                .v_align_middle();
+        }
 
-struct Camera {
-    position: Vec3,
-    rotation: Mat3,
-    viewport: Viewport,
-    buffer: Vec<char>,
-}
+        gfx.render_to(&texture, &text);
+        gfx.render_to(&texture, &dots);
 
-impl Camera {
-    fn camera_pixel_to_viewport_distance(&self, x: f32, y: f32) -> Vec3 {
-        Vec3 {
-            x: x * self.viewport.width / COLS as f32,
-            y: y * self.viewport.height / ROWS as f32,
-            z: D,
-        }
+        GlyphAtlas { texture, offsets }
     }
 }
 
 #[derive(AppState)]
 struct State {
     font: Font,
+    atlas: GlyphAtlas,
+    // The camera being looked through and moved, a working copy of the one
+    // at `active` in the list of every camera there is to switch between.
     camera: Camera,
-    spheres: Vec<Sphere>,
+    cameras: Vec<(String, Camera)>,
+    active: usize,
+    scene: Scene,
+    renderer: RenderWorker,
+    selected: Option<usize>,
+    hud: bool,
+    paused: bool,
+    time: f32,
+    gamepad: Option<Gamepad>,
+    menu: bool,
+    timings: FrameTimings,
+    // A running average of how long a whole frame at the current resolution
+    // takes to trace, in seconds.
+    trace_cost: Option<f32>,
+    recorder: Option<Recorder>,
+    // Recorded frames still to be replayed, and how many have been so far.
+    replay: Option<std::vec::IntoIter<InputFrame>>,
+    replayed: usize,
+    // The scene file, reloaded whenever it's saved.
+    watcher: Option<FileWatcher>,
+    // Where each traced frame is also written, and the seconds since startup
+    // it's timed by.
+    ascii: Option<AsciiWriter>,
+    elapsed: f32,
+    // The last thing a key did or that went wrong, shown along the bottom of
+    // the window for STATUS_TIME after it happened.
+    status: Option<(String, Instant)>,
+}
+
+// Where the last frame's time went, for the HUD. Tracing happens on the
+// render worker and is timed from the last frame it finished. Submitting
+// only covers handing the batch to the GPU, which then draws it
+// asynchronously.
+#[derive(Default)]
+struct FrameTimings {
+    trace: Duration,
+    build: Duration,
+    submit: Duration,
 }
 
 #[notan_main]
 fn main() -> Result<(), String> {
+    let settings = Settings::from_args()?;
+
+    let mut scene = match (&settings.scene, settings.generate) {
+        (Some(path), _) => load_scene(path)?,
+        (None, generator) => Scene {
+            objects: match generator {
+                Some(generator) => generate(generator, settings.seed, settings.object_count),
+                None => default_scene(),
+            },
+            meshes: Vec::new(),
+            animations: Vec::new(),
+            bodies: Vec::new(),
+            background: None,
+            bookmarks: Vec::new(),
+            cameras: Vec::new(),
+        },
+    };
+
+    // With a camera path the frames are rendered straight to disk and no
+    // window is opened.
+    if let Some(path) = &settings.camera_path {
+        return render_animation(&settings, path, &mut scene);
+    }
+
     let win_config = WindowConfig::new()
         .set_size(WIDTH as u32, HEIGHT as u32)
         .set_title("Cast")
@@ -62,312 +225,854 @@ fn main() -> Result<(), String> {
         .set_resizable(true)
         .set_min_size(600, 400);
 
-    notan::init_with(setup)
-        .initialize(init)
-        .add_config(win_config)
-        .add_config(TextConfig)
-        .update(update)
-        .draw(draw)
-        .build()
+    let recorder = settings
+        .record
+        .as_deref()
+        .map(Recorder::create)
+        .transpose()?;
+    let session = settings.replay.as_deref().map(load_session).transpose()?;
+    let ascii = settings
+        .ascii
+        .as_deref()
+        .map(|path| AsciiWriter::create(path, settings.ascii_format))
+        .transpose()?;
+
+    notan::init_with(move |gfx: &mut Graphics| {
+        setup(gfx, settings, scene, recorder, session, ascii)
+    })
+    .add_config(win_config)
+    .add_config(TextConfig)
+    .add_config(DrawConfig)
+    .add_config(EguiConfig)
+    .update(update)
+    .draw(draw)
+    .build()
 }
 
-fn setup(gfx: &mut Graphics) -> State {
+fn setup(
+    gfx: &mut Graphics,
+    settings: Settings,
+    scene: Scene,
+    recorder: Option<Recorder>,
+    session: Option<Vec<InputFrame>>,
+    ascii: Option<AsciiWriter>,
+) -> State {
     let font = gfx
         .create_font(include_bytes!("../assets/fonts/NotoSansMono-Regular.ttf"))
         .unwrap();
 
-    let camera = Camera {
-        position: Vec3::default(),
-        rotation: Mat3::default(),
-        viewport: Viewport {
-            width: 1.0,
-            height: 1.0,
-        },
-        buffer: Vec::with_capacity(COLS * ROWS),
-    };
+    let mut glyphs: Vec<char> = RAMPS
+        .iter()
+        .flat_map(|ramp| ramp.chars())
+        .copied()
+        .collect();
+    glyphs.extend((0x2800..0x2900).filter_map(char::from_u32));
+    glyphs.sort();
+    glyphs.dedup();
+
+    let atlas = GlyphAtlas::new(gfx, &font, &glyphs);
+    let camera = Camera::new(&settings);
+    let mut cameras = vec![(String::from("free"), camera.clone())];
+    cameras.extend(scene_cameras(&scene, &settings));
+    let watcher = settings.scene.as_deref().map(FileWatcher::new);
+    let gamepad = Gamepad::open();
+    let status = gamepad
+        .is_some()
+        .then(|| (format!("using gamepad {GAMEPAD_DEVICE}"), Instant::now()));
 
     State {
         font,
+        atlas,
         camera,
-        spheres: Vec::new(),
+        cameras,
+        active: 0,
+        scene,
+        renderer: RenderWorker::new(settings),
+        selected: None,
+        hud: false,
+        paused: false,
+        time: 0.0,
+        gamepad,
+        menu: false,
+        timings: FrameTimings::default(),
+        trace_cost: None,
+        recorder,
+        replay: session.map(Vec::into_iter),
+        replayed: 0,
+        watcher,
+        ascii,
+        elapsed: 0.0,
+        status,
     }
 }
 
-fn init(state: &mut State) {
-    state.spheres = vec![
-        Sphere {
-            center: Vec3 {
-                x: 0.0,
-                y: -1.0,
-                z: 3.0,
-            },
-            radius: 1.0,
-        },
-        Sphere {
-            center: Vec3 {
-                x: 2.0,
-                y: 0.0,
-                z: 4.0,
-            },
-            radius: 1.0,
-        },
-        Sphere {
-            center: Vec3 {
-                x: -2.0,
-                y: 0.0,
-                z: 4.0,
-            },
-            radius: 1.0,
-        },
-        Sphere {
-            center: Vec3 {
-                x: 0.0,
-                y: -5001.0,
-                z: 0.0,
-            },
-            radius: 5000.0,
-        },
-    ];
+// How long a status message stays on screen.
+const STATUS_TIME: Duration = Duration::from_millis(2500);
+
+fn show_status(state: &mut State, message: impl Into<String>) {
+    state.status = Some((message.into(), Instant::now()));
 }
 
-fn ray_intersects_triangle(
-    ray_origin: Vec3,
-    ray_direction: Vec3,
-    triangle: &Triangle,
-) -> Option<(Vec3, Vec3)> {
-    const EPSILON: f32 = 1e-6;
+// Errors also go to stderr, where they outlast the status line.
+fn show_error(state: &mut State, message: String) {
+    eprintln!("{message}");
+    show_status(state, message);
+}
 
-    let triangle_normal = (triangle.vertex2 - triangle.vertex1)
-        .cross(triangle.vertex3 - triangle.vertex1)
-        .normalize();
+// The scene's own cameras, after the one the session starts with.
+fn scene_cameras(scene: &Scene, settings: &Settings) -> Vec<(String, Camera)> {
+    scene
+        .cameras
+        .iter()
+        .map(|scene_camera| {
+            let mut camera = Camera::new(settings);
+            camera.position = scene_camera.position;
+            camera.rotation = scene_camera.rotation;
+            if let Some(fov) = scene_camera.fov {
+                camera.set_fov(fov);
+            }
+            (scene_camera.name.clone(), camera)
+        })
+        .collect()
+}
 
-    let triangle_d = -triangle_normal.dot(triangle.vertex1);
+// Looks through another camera, leaving the last one where it was to come
+// back to.
+fn switch_camera(state: &mut State, index: usize) {
+    if index == state.active {
+        return;
+    }
+
+    state.cameras[state.active].1 = state.camera.clone();
+    state.active = index;
+    state.camera = state.cameras[index].1.clone();
+    state.selected = None;
+    show_status(state, format!("camera: {}", state.cameras[state.active].0));
+    state.renderer.cache.invalidate();
+}
+
+// Asks for a frame from the active camera, and from the camera after it in
+// the list too when the layout shows a second view.
+fn request_frame(state: &mut State) {
+    let count = state.cameras.len();
+    let second = (state.renderer.settings.layout != ViewLayout::Single && count > 1)
+        .then(|| &state.cameras[(state.active + 1) % count].1);
+    state.renderer.request(&state.scene, &state.camera, second);
+}
+
+fn save_screenshot(state: &State) -> Result<String, String> {
+    let settings = &state.renderer.settings;
+    let (width, height) = (settings.image_width, settings.image_height);
+
+    let pixels = render_image(&state.scene, &state.camera, settings);
+
+    let path = format!(
+        "cast-{}.{}",
+        timestamp()?,
+        image_extension(settings.image_format)
+    );
+    write_image(&path, settings.image_format, width, height, &pixels)?;
+
+    Ok(path)
+}
+
+// Seconds since the Unix epoch, for naming saved files. Browsers only have
+// the clock through JavaScript.
+#[cfg(not(target_arch = "wasm32"))]
+fn timestamp() -> Result<u64, String> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+    Ok(now.map_err(|e| e.to_string())?.as_secs())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn timestamp() -> Result<u64, String> {
+    Ok((js_sys::Date::now() / 1000.0) as u64)
+}
+
+// Writes the scene as it is now, including anything added or moved while
+// running, to a new file that can be loaded again with --scene.
+fn snapshot_scene(state: &mut State) -> Result<String, String> {
+    let path = format!("cast-scene-{}.json", timestamp()?);
+
+    save_scene(&mut state.scene, state.time, &path)?;
 
-    let denominator = ray_direction.dot(triangle_normal);
+    Ok(path)
+}
 
-    if denominator.abs() < EPSILON {
-        return None; // Ray is parallel to the triangle plane
+fn use_bookmarks(app: &App, state: &mut State) {
+    let (save, recall) = (app.keyboard.ctrl(), app.keyboard.shift());
+    if !save && !recall {
+        return;
     }
 
-    let t = -(triangle_normal.dot(ray_origin) + triangle_d) / denominator;
+    for (slot, key) in (1..).zip(BOOKMARK_KEYS) {
+        if !app.keyboard.was_pressed(key) {
+            continue;
+        }
 
-    if t < EPSILON {
-        return None; // Intersection point is behind the ray origin
+        if save {
+            save_bookmark(&mut state.scene, slot, &state.camera);
+            show_status(state, format!("saved bookmark {slot}"));
+        } else if recall_bookmark(&state.scene, slot, &mut state.camera) {
+            state.renderer.cache.invalidate();
+        }
     }
+}
 
-    let intersection_point = ray_origin + ray_direction * t;
+// The keys held this frame, and which of the keyboard's actions they ask for.
+fn held_actions(app: &App) -> Vec<Action> {
+    HELD_KEYS
+        .iter()
+        .filter(|(key, _)| app.keyboard.is_down(*key))
+        .map(|(_, action)| *action)
+        .collect()
+}
 
-    // Check if the intersection point is inside the triangle using barycentric coordinates
-    let e1 = triangle.vertex2 - triangle.vertex1;
-    let e2 = triangle.vertex3 - triangle.vertex1;
-    let q = intersection_point - triangle.vertex1;
+fn edit_objects(app: &App, state: &mut State, held: &[Action]) {
+    let camera = &state.camera;
+    let cache = &mut state.renderer.cache;
+
+    // Number keys with Ctrl or Shift held are bookmarks instead.
+    let plain = !app.keyboard.ctrl() && !app.keyboard.shift();
+    let shape = SPAWN_KEYS
+        .iter()
+        .find(|(key, _)| plain && app.keyboard.was_pressed(*key))
+        .map(|(_, shape)| *shape);
+
+    if let Some(shape) = shape {
+        let object = spawn(shape, camera);
+        cache.invalidate_object(camera, &object);
+        state.scene.objects.push(object);
+        state.selected = Some(state.scene.objects.len() - 1);
+    }
 
-    let u = q.dot(e1) / e1.length_squared();
-    let v = q.dot(e2) / e2.length_squared();
+    if app.keyboard.was_pressed(KeyCode::B) {
+        let ball = drop_ball(&mut state.scene, camera);
+        cache.invalidate_object(camera, &state.scene.objects[ball]);
+    }
 
-    if u >= 0.0 && v >= 0.0 && u + v <= 1.0 {
-        Some((intersection_point, triangle_normal))
-    } else {
-        None
+    // Pick whatever is under the crosshair in the middle of the screen.
+    if app.mouse.left_was_pressed() {
+        let (origin, direction) = camera.generate_ray(0.0, 0.0);
+        state.selected = closest_hit(origin, direction, 1.0, f32::INFINITY, &state.scene.objects)
+            .map(|(i, _)| i);
+
+        // Set directly, as `camera` still borrows the state.
+        let message = match state.selected {
+            Some(i) => format!("selected: {} {}", state.scene.objects[i].name(), i),
+            None => "selected: nothing".to_string(),
+        };
+        state.status = Some((message, Instant::now()));
     }
-}
 
-fn ray_intersects_cuboid_no_rotation(
-    origin: Vec3,
-    direction: Vec3,
-    position: Vec3,
-    half_extents: Vec3,
-) -> Option<(Vec3, Vec3)> {
-    let inv_direction = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+    let Some(i) = state.selected else {
+        return;
+    };
+
+    if app.keyboard.was_pressed(KeyCode::Delete) || app.keyboard.was_pressed(KeyCode::Back) {
+        cache.invalidate_object(camera, &state.scene.objects[i]);
+        remove_object(&mut state.scene, i);
+        state.selected = None;
+        return;
+    }
 
-    let t1 = (position - origin) * inv_direction;
-    let t2 = (position + half_extents - origin) * inv_direction;
+    if let Some(edit) = Edit::from_held(camera, held) {
+        let object = &mut state.scene.objects[i];
+        cache.invalidate_object(camera, object);
+        edit.apply(object);
+        cache.invalidate_object(camera, object);
+    }
+}
 
-    let tmin = t1.min(t2);
-    let tmax = t1.max(t2);
+// Input is captured or replayed first, so that everything after sees exactly
+// what was recorded, down to the frame time.
+fn update(app: &mut App, state: &mut State) {
+    let dt = replay_input(app, state);
+    state.elapsed += dt;
+
+    if let Some(recorder) = &mut state.recorder {
+        let axes = state.gamepad.as_ref().map(Gamepad::axes);
+        if let Err(e) = recorder.record(app, dt, axes) {
+            eprintln!("failed to record input: {e}");
+            state.recorder = None;
+        }
+    }
 
-    let t_enter = tmin.max_element();
-    let t_exit = tmax.min_element();
+    advance(app, state, dt);
 
-    if t_exit < 0.0 || t_enter > t_exit {
-        return None; // No intersection or behind the ray origin
+    if state.replay.is_some() && state.renderer.settings.export {
+        if let Err(e) = export_frame(state) {
+            eprintln!("failed to export frame: {e}");
+        }
     }
+}
 
-    let intersection_point = origin + direction * t_enter;
-    let normal = compute_cuboid_normal(intersection_point, position, half_extents);
+// Swaps in the next recorded frame for the live input, returning the time it
+// advanced by. Once the session runs out control goes back to the keyboard,
+// or the app exits if the frames were being exported.
+fn replay_input(app: &mut App, state: &mut State) -> f32 {
+    let Some(replay) = &mut state.replay else {
+        return app.timer.delta_f32();
+    };
 
-    Some((intersection_point, normal))
+    let Some(frame) = replay.next() else {
+        show_status(
+            state,
+            format!("replay finished after {} frames", state.replayed),
+        );
+        state.replay = None;
+        app.keyboard.down.clear();
+        app.keyboard.pressed.clear();
+        state.gamepad = Gamepad::open();
+
+        if state.renderer.settings.export {
+            app.exit();
+        }
+        return app.timer.delta_f32();
+    };
+
+    frame.apply(app);
+    state.gamepad = frame.axes.map(Gamepad::fixed);
+    state.replayed += 1;
+    frame.dt
 }
 
-fn compute_cuboid_normal(point: Vec3, position: Vec3, half_extents: Vec3) -> Vec3 {
-    let local_point = point - position;
-    let mut normal = Vec3::default();
+// Writes the frame just replayed as an image. It is traced here in full, so
+// it doesn't depend on how far behind the render worker is.
+fn export_frame(state: &State) -> Result<(), String> {
+    let settings = &state.renderer.settings;
+    std::fs::create_dir_all(&settings.output).map_err(|e| e.to_string())?;
+
+    let pixels = render_image(&state.scene, &state.camera, settings);
+    let path = format!(
+        "{}/frame_{:04}.{}",
+        settings.output,
+        state.replayed - 1,
+        image_extension(settings.image_format)
+    );
+    write_image(
+        &path,
+        settings.image_format,
+        settings.image_width,
+        settings.image_height,
+        &pixels,
+    )
+}
 
-    for i in 0..3 {
-        if local_point[i].abs() + 1e-6 > half_extents[i] {
-            normal[i] = local_point[i].signum();
+// Simulation time advanced by one single step while paused.
+const STEP_TIME: f32 = 1.0 / 60.0;
+
+fn advance(app: &mut App, state: &mut State, dt: f32) {
+    if state.renderer.receive() {
+        state.timings.trace = state.renderer.trace_time;
+        let settings = &mut state.renderer.settings;
+        let cells = state.renderer.stats.cells;
+        let trace_time = state.renderer.trace_time;
+        if adjust_resolution(settings, &mut state.trace_cost, cells, trace_time) {
+            state.renderer.cache.invalidate();
+        }
+
+        if let Some(ascii) = &mut state.ascii {
+            if let Err(e) = ascii.write_frame(state.elapsed, &state.renderer.frame) {
+                eprintln!("failed to write ascii frame: {e}");
+                state.ascii = None;
+            }
         }
     }
 
-    normal
-}
+    if state.watcher.as_mut().is_some_and(FileWatcher::changed) {
+        reload_scene(state);
+    }
 
-fn ray_intersects_sphere(origin: Vec3, direction: Vec3, sphere: &Sphere) -> (f32, f32) {
-    let r = sphere.radius;
+    if app.keyboard.was_pressed(KeyCode::Tab) || app.keyboard.was_pressed(KeyCode::Escape) {
+        state.menu = !state.menu;
+    }
 
-    let co = origin - sphere.center;
+    if app.keyboard.was_pressed(KeyCode::F3) {
+        state.hud = !state.hud;
+    }
 
-    let a = direction.dot(direction);
-    let b = 2.0 * co.dot(direction);
-    let c = co.dot(co) - r * r;
+    if app.keyboard.was_pressed(KeyCode::F4) {
+        state.renderer.settings.mode = match state.renderer.settings.mode {
+            RenderMode::Ramp => RenderMode::Braille,
+            RenderMode::Braille => RenderMode::Ramp,
+        };
+        state.renderer.cache.invalidate();
+    }
 
-    let discriminant = b * b - 4.0 * a * c;
-    if discriminant < 0.0 {
-        return (f32::INFINITY, f32::INFINITY);
+    if app.keyboard.was_pressed(KeyCode::F5) {
+        let next = SAMPLE_COUNTS
+            .iter()
+            .position(|n| *n == state.renderer.settings.samples)
+            .map_or(0, |i| (i + 1) % SAMPLE_COUNTS.len());
+        state.renderer.settings.samples = SAMPLE_COUNTS[next];
+        show_status(
+            state,
+            format!("samples per cell: {}", state.renderer.settings.samples),
+        );
+        state.renderer.cache.invalidate();
     }
 
-    let t1 = (-b + discriminant.sqrt()) / (2.0 * a);
-    let t2 = (-b - discriminant.sqrt()) / (2.0 * a);
+    if app.keyboard.was_pressed(KeyCode::F8) {
+        state.camera.projection = state.camera.projection.next();
+        show_status(
+            state,
+            format!("projection: {}", state.camera.projection.name()),
+        );
+        state.renderer.cache.invalidate();
+    }
 
-    (t1, t2)
-}
+    if app.keyboard.was_pressed(KeyCode::F9) {
+        state.renderer.settings.fog_enabled = !state.renderer.settings.fog_enabled;
+        show_status(
+            state,
+            format!("fog: {}", state.renderer.settings.fog_enabled),
+        );
+        state.renderer.cache.invalidate();
+    }
 
-fn compute_lighting(p: Vec3, n: Vec3, player_pos: Vec3) -> char {
-    let mut i = 0.2;
+    if app.keyboard.was_pressed(KeyCode::F10) {
+        state.renderer.settings.debug_view = state.renderer.settings.debug_view.next();
+        show_status(
+            state,
+            format!("debug view: {}", state.renderer.settings.debug_view.name()),
+        );
+        state.renderer.cache.invalidate();
+    }
 
-    // let light_pos = Vec3 {
-    //     x: 2.0,
-    //     y: 1.0,
-    //     z: -3.0,
-    // };
-    let light_pos = player_pos;
+    if app.keyboard.was_pressed(KeyCode::F7) {
+        state.renderer.settings.quality = !state.renderer.settings.quality;
+        show_status(
+            state,
+            format!("path tracing: {}", state.renderer.settings.quality),
+        );
+        state.renderer.cache.invalidate();
+    }
 
-    let l = light_pos - p;
+    if app.keyboard.was_pressed(KeyCode::F6) {
+        state.renderer.settings.reproject = !state.renderer.settings.reproject;
+        show_status(
+            state,
+            format!("reprojection: {}", state.renderer.settings.reproject),
+        );
+    }
 
-    let n_dot_l = n.dot(l);
-    if n_dot_l > 0.0 {
-        i += 0.6 * n_dot_l / (n.length() * l.length());
+    if app.keyboard.was_pressed(KeyCode::F2) {
+        state.renderer.settings.ramp = state.renderer.settings.ramp.next();
+        show_status(
+            state,
+            format!("character ramp: {}", state.renderer.settings.ramp.name()),
+        );
+        state.renderer.cache.invalidate();
     }
 
-    let scale = [
-        '.', ',', ':', ';', '*', '+', 'o', 'x', '%', '&', '#', '$', '@', '9',
-    ];
-    let index = (i * scale.len() as f32) as usize;
-    scale[index]
-}
+    if app.keyboard.was_pressed(KeyCode::F11) {
+        state.renderer.settings.movement = state.renderer.settings.movement.next();
+        show_status(
+            state,
+            format!("movement: {}", state.renderer.settings.movement.name()),
+        );
+    }
 
-fn trace_ray(origin: Vec3, direction: Vec3, t_min: f32, t_max: f32, spheres: &[Sphere]) -> char {
-    let mut closest_t: f32 = f32::INFINITY;
-    let mut closest_sphere: Option<&Sphere> = None;
+    if app.keyboard.was_pressed(KeyCode::C) {
+        match state.cameras.len() {
+            1 => show_status(state, "the scene has no cameras to switch to"),
+            count => switch_camera(state, (state.active + 1) % count),
+        }
+    }
 
-    for sphere in spheres {
-        let (t1, t2) = ray_intersects_sphere(origin, direction, sphere);
+    if app.keyboard.was_pressed(KeyCode::V) {
+        state.renderer.settings.layout = state.renderer.settings.layout.next();
+        show_status(
+            state,
+            format!("layout: {}", state.renderer.settings.layout.name()),
+        );
+    }
 
-        if t_min < t1 && t1 < t_max && t1 < closest_t {
-            closest_t = t1;
-            closest_sphere = Some(sphere);
+    if app.keyboard.was_pressed(KeyCode::F12) {
+        match save_screenshot(state) {
+            Ok(path) => show_status(state, format!("saved screenshot to {path}")),
+            Err(e) => show_error(state, format!("failed to save screenshot: {e}")),
         }
-        if t_min < t2 && t2 < t_max && t2 < closest_t {
-            closest_t = t2;
-            closest_sphere = Some(sphere);
+    }
+
+    if app.keyboard.ctrl() && app.keyboard.was_pressed(KeyCode::S) {
+        match snapshot_scene(state) {
+            Ok(path) => show_status(state, format!("saved scene to {path}")),
+            Err(e) => show_error(state, format!("failed to save scene: {e}")),
         }
     }
 
-    let triangle = Triangle {
-        vertex1: Vec3::new(0.0, -1.0, 1.0),
-        vertex2: Vec3::new(3.0, -1.0, -1.0),
-        vertex3: Vec3::new(1.0, 2.0, 1.0),
-    };
+    // The settings window has the keyboard and mouse while it's open, so
+    // using its widgets doesn't also fly the camera or edit the scene.
+    if !state.menu {
+        use_bookmarks(app, state);
+    }
 
-    if let Some((intersection_point, normal)) =
-        ray_intersects_triangle(origin, direction, &triangle)
-    {
-        if intersection_point.length() < closest_t {
-            return compute_lighting(intersection_point, normal.normalize(), origin);
-        }
+    if app.keyboard.was_pressed(KeyCode::P) {
+        state.paused = !state.paused;
+        show_status(state, format!("paused: {}", state.paused));
+    }
+
+    // While paused nothing moves, until a single step is requested. Frames
+    // are still requested, in case the last one was skipped while another
+    // was in flight.
+    let step = state.paused && app.keyboard.was_pressed(KeyCode::N);
+    if state.paused && !step {
+        request_frame(state);
+        return;
     }
 
-    // Cuboid transformation (rotation, translation, etc.)
-    let cuboid_position = Vec3::new(-1.0, 0.0, 3.0);
-    let cuboid_half_extents = Vec3::new(1.0, 1.0, 1.0); // Half extents along each axis
+    if !state.menu {
+        let held = held_actions(app);
+        let settings = &state.renderer.settings;
+        let before = state.camera.position;
 
-    let pp =
-        ray_intersects_cuboid_no_rotation(origin, direction, cuboid_position, cuboid_half_extents);
-    if let Some((pt, nt)) = pp {
-        if pt.length() < closest_t {
-            return compute_lighting(pt, nt / nt.length(), origin);
+        // Ctrl chords such as Ctrl+S are commands, not movement.
+        if !app.keyboard.ctrl() {
+            move_camera(&mut state.camera, &held, settings);
         }
+        if let Some(gamepad) = &state.gamepad {
+            apply_gamepad(gamepad.axes(), &mut state.camera, settings);
+        }
+        let motion = state.camera.position - before;
+        state.camera.position =
+            constrain_movement(before, motion, &state.scene.objects, settings.movement);
+
+        if adjust_lens(&mut state.camera, &held) {
+            state.renderer.cache.invalidate();
+        }
+        edit_objects(app, state, &held);
     }
 
-    if let Some(s) = closest_sphere {
-        let p = origin + closest_t * direction;
-        let n = p - s.center;
+    // Simulation time moves on independently of how the frame is traced.
+    let dt = if step { STEP_TIME } else { dt };
+    let from = state.time;
+    state.time += dt;
+    let (camera, cache) = (&state.camera, &mut state.renderer.cache);
+    step_scene(&mut state.scene, from, state.time, |object| {
+        cache.invalidate_object(camera, object)
+    });
 
-        return compute_lighting(p, n / n.length(), origin);
-    }
+    request_frame(state);
+}
+
+// Swaps in the scene as it is now on disk, keeping the camera where it is.
+// Animations are caught up to the current time so they carry on rather than
+// jumping back to the start. A file that doesn't load, say one saved half way
+// through an edit, leaves the old scene up until the next save.
+fn reload_scene(state: &mut State) {
+    let Some(path) = state.watcher.as_ref().map(|w| w.path().to_string()) else {
+        return;
+    };
 
-    ' '
+    match load_scene(&path) {
+        Ok(mut scene) => {
+            animate(&mut scene.objects, &scene.animations, 0.0, state.time);
+            show_status(state, format!("reloaded {path}"));
+
+            // The cameras are swapped too, unless the one in use is gone.
+            state.cameras.truncate(1);
+            state
+                .cameras
+                .extend(scene_cameras(&scene, &state.renderer.settings));
+            if state.active >= state.cameras.len() {
+                state.active = 0;
+                state.camera = state.cameras[0].1.clone();
+            }
+            state.scene = scene;
+            state.selected = None;
+            state.renderer.cache.invalidate();
+        }
+        Err(e) => show_error(state, format!("failed to reload scene: {e}")),
+    }
 }
 
-fn update(app: &mut App, state: &mut State) {
-    if app.keyboard.is_down(KeyCode::W) {
-        state.camera.position += state.camera.rotation * Vec3::from_array([0.0, 0.0, 0.05]);
+fn draw(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut State) {
+    let start = Instant::now();
+    let mut draw = gfx.create_draw();
+    draw.clear(Color::BLACK);
+
+    let buffer = &state.renderer.frame.cells;
+
+    // The buffer starts at the bottom row of the viewport, so rows are flipped
+    // when placed on screen.
+    for (i, c) in buffer.iter().enumerate() {
+        if let Some(&offset) = state.atlas.offsets.get(c) {
+            let x = (i % COLS * CELL_WIDTH) as f32;
+            let y = ((ROWS - 1 - i / COLS) * CELL_HEIGHT) as f32;
+
+            draw.image(&state.atlas.texture)
+                .position(x, y)
+                .size(CELL_WIDTH as f32, CELL_HEIGHT as f32)
+                .crop(offset, (CELL_WIDTH as f32, CELL_HEIGHT as f32));
+        }
     }
-    if app.keyboard.is_down(KeyCode::S) {
-        state.camera.position -= state.camera.rotation * Vec3::from_array([0.0, 0.0, 0.05]);
+
+    // Crosshair for picking objects with the mouse.
+    if let Some(&offset) = state.atlas.offsets.get(&'+') {
+        let x = (COLS / 2 * CELL_WIDTH) as f32;
+        let y = ((ROWS - 1 - ROWS / 2) * CELL_HEIGHT) as f32;
+
+        draw.image(&state.atlas.texture)
+            .position(x, y)
+            .size(CELL_WIDTH as f32, CELL_HEIGHT as f32)
+            .crop(offset, (CELL_WIDTH as f32, CELL_HEIGHT as f32))
+            .color(Color::RED);
     }
-    if app.keyboard.is_down(KeyCode::A) {
-        state.camera.position -= state.camera.rotation * Vec3::from_array([0.05, 0.0, 0.0]);
+
+    // Written before the frame is submitted so the background can be sized to
+    // fit, which leaves the build and submit times on it a frame behind.
+    let hud = state.hud.then(|| hud_text(app, state));
+    if let Some(hud) = &hud {
+        let lines = hud.lines().count();
+        draw.rect((0.0, 0.0), (HUD_WIDTH, lines as f32 * CELL_HEIGHT as f32))
+            .color(Color::new(0.0, 0.0, 0.0, 0.75));
     }
-    if app.keyboard.is_down(KeyCode::D) {
-        state.camera.position += state.camera.rotation * Vec3::from_array([0.05, 0.0, 0.0]);
+
+    // The status line sits on the bottom row, whether or not the HUD is up.
+    let status = state
+        .status
+        .as_ref()
+        .filter(|(_, shown)| shown.elapsed() < STATUS_TIME)
+        .map(|(message, _)| message.clone());
+    let status_y = (HEIGHT - CELL_HEIGHT) as f32;
+    if let Some(status) = &status {
+        let width = (status.chars().count() + 2) * CELL_WIDTH;
+        draw.rect((0.0, status_y), (width as f32, CELL_HEIGHT as f32))
+            .color(Color::new(0.0, 0.0, 0.0, 0.75));
     }
-    if app.keyboard.is_down(KeyCode::E) {
-        state.camera.rotation *= Mat3::from_rotation_y(0.025);
+
+    let built = Instant::now();
+    gfx.render(&draw);
+    state.timings.build = built - start;
+    state.timings.submit = built.elapsed();
+
+    if let Some(hud) = hud {
+        let mut text = gfx.create_text();
+        text.add(&hud)
+            .font(&state.font)
+            .size(FONT_SIZE)
+            .position(CELL_WIDTH as f32, 0.0)
+            .color(Color::YELLOW);
+        gfx.render(&text);
     }
-    if app.keyboard.is_down(KeyCode::Q) {
-        state.camera.rotation *= Mat3::from_rotation_y(0.025).inverse();
+
+    if let Some(status) = status {
+        let mut text = gfx.create_text();
+        text.add(&status)
+            .font(&state.font)
+            .size(FONT_SIZE)
+            .position(CELL_WIDTH as f32, status_y)
+            .color(Color::YELLOW);
+        gfx.render(&text);
     }
 
-    let rows = ROWS as i32;
-    let cols = COLS as i32;
-    state.camera.buffer = (0..rows * cols)
-        .into_par_iter()
-        .map(|i| {
-            let x = (i % cols) - (cols / 2);
-            let y = (i / cols) - (rows / 2);
+    if state.menu {
+        let output = plugins.egui(|ctx| settings_window(ctx, state));
+        gfx.render(&output);
+    }
+}
 
-            let position = state.camera.position;
-            let rotation = state.camera.rotation;
-            let direction: Vec3 = rotation
-                * state
-                    .camera
-                    .camera_pixel_to_viewport_distance(x as f32, y as f32);
+// The top of the ray budget slider, a few secondary rays for every path of a
+// frame at the most samples per cell.
+const RAY_BUDGET_MAX: usize = 4_000_000;
+
+// Tab or Esc opens this window over the view. Anything that changes what the
+// frame looks like throws away the cached frame.
+fn settings_window(ctx: &egui::Context, state: &mut State) {
+    let mut changed = false;
+    let mut active = state.active;
+
+    egui::Window::new("Settings").show(ctx, |ui| {
+        let settings = &mut state.renderer.settings;
+
+        ui.heading("Controls");
+        egui::ComboBox::from_label("movement")
+            .selected_text(settings.movement.name())
+            .show_ui(ui, |ui| {
+                for movement in MOVEMENTS {
+                    ui.selectable_value(&mut settings.movement, movement, movement.name());
+                }
+            });
+        ui.add(egui::Slider::new(&mut settings.move_speed, 0.005..=0.5).text("move speed"));
+        ui.add(egui::Slider::new(&mut settings.look_speed, 0.005..=0.1).text("look speed"));
+        ui.add(
+            egui::Slider::new(&mut settings.gamepad_sensitivity, 0.1..=4.0)
+                .text("gamepad sensitivity"),
+        );
+        ui.add(egui::Slider::new(&mut settings.deadzone, 0.0..=0.9).text("gamepad deadzone"));
+
+        ui.separator();
+        ui.heading("Camera");
+        egui::ComboBox::from_label("camera")
+            .selected_text(state.cameras[active].0.as_str())
+            .show_ui(ui, |ui| {
+                for (i, (name, _)) in state.cameras.iter().enumerate() {
+                    ui.selectable_value(&mut active, i, name.as_str());
+                }
+            });
+        egui::ComboBox::from_label("layout")
+            .selected_text(settings.layout.name())
+            .show_ui(ui, |ui| {
+                for layout in VIEW_LAYOUTS {
+                    ui.selectable_value(&mut settings.layout, layout, layout.name());
+                }
+            });
+        let mut fov = state.camera.fov();
+        if ui
+            .add(egui::Slider::new(&mut fov, 10.0..=170.0).text("field of view"))
+            .changed()
+        {
+            state.camera.set_fov(fov);
+            changed = true;
+        }
+        changed |= ui
+            .add(egui::Slider::new(&mut state.camera.aperture, 0.0..=0.5).text("aperture"))
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut state.camera.focus_distance, 0.1..=20.0)
+                    .text("focus distance"),
+            )
+            .changed();
+        let mut automatic = settings.aspect.is_none();
+        let mut aspect = state.camera.aspect;
+        let mut reshaped = ui.checkbox(&mut automatic, "automatic aspect").changed();
+        reshaped |= ui
+            .add_enabled(
+                !automatic,
+                egui::Slider::new(&mut aspect, 0.25..=4.0).text("aspect"),
+            )
+            .changed();
+        if reshaped {
+            settings.aspect = (!automatic).then_some(aspect);
+            state
+                .camera
+                .set_aspect(settings.aspect.unwrap_or(GRID_ASPECT));
+            changed = true;
+        }
 
-            trace_ray(position, direction, 1.0, f32::INFINITY, &state.spheres)
-        })
-        .collect();
+        ui.separator();
+        ui.heading("Rendering");
+        egui::ComboBox::from_label("samples per cell")
+            .selected_text(settings.samples.to_string())
+            .show_ui(ui, |ui| {
+                for n in SAMPLE_COUNTS {
+                    changed |= ui
+                        .selectable_value(&mut settings.samples, n, n.to_string())
+                        .changed();
+                }
+            });
+        egui::ComboBox::from_label("debug view")
+            .selected_text(settings.debug_view.name())
+            .show_ui(ui, |ui| {
+                for view in DEBUG_VIEWS {
+                    changed |= ui
+                        .selectable_value(&mut settings.debug_view, view, view.name())
+                        .changed();
+                }
+            });
+        egui::ComboBox::from_label("character ramp")
+            .selected_text(settings.ramp.name())
+            .show_ui(ui, |ui| {
+                for ramp in RAMPS {
+                    changed |= ui
+                        .selectable_value(&mut settings.ramp, ramp, ramp.name())
+                        .changed();
+                }
+            });
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.gamma, 0.2..=3.0).text("gamma"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.lighting.ambient, 0.0..=1.0).text("ambient light"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.lighting.diffuse, 0.0..=1.0).text("diffuse light"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.max_bounces, 1..=16).text("max bounces"))
+            .changed();
+        // Zero, at the far left, lifts the limit.
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut settings.ray_budget, 0..=RAY_BUDGET_MAX)
+                    .logarithmic(true)
+                    .text("ray budget"),
+            )
+            .changed();
+        egui::ComboBox::from_label("resolution")
+            .selected_text(format!("1/{}", settings.resolution))
+            .show_ui(ui, |ui| {
+                for r in RESOLUTIONS {
+                    changed |= ui
+                        .selectable_value(&mut settings.resolution, r, format!("1/{r}"))
+                        .changed();
+                }
+            });
+        ui.add(egui::Slider::new(&mut settings.target_fps, 0.0..=120.0).text("target fps"));
+        changed |= ui.checkbox(&mut settings.quality, "path tracing").changed();
+        changed |= ui.checkbox(&mut settings.fog_enabled, "fog").changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.fog_density, 0.0..=1.0).text("fog density"))
+            .changed();
+        ui.checkbox(&mut settings.reproject, "reprojection");
+    });
+
+    if changed {
+        state.renderer.cache.invalidate();
+    }
+    switch_camera(state, active);
 }
 
-fn draw(app: &mut App, gfx: &mut Graphics, state: &mut State) {
-    let mut text = gfx.create_text();
-    text.clear_options(ClearOptions::color(Color::BLACK));
-
-    let display: String = state
-        .camera
-        .buffer
-        .par_chunks(COLS)
-        .map(|chunk: &[char]| chunk.iter().collect::<String>() + "\n")
-        .rev()
-        .collect();
+const HUD_WIDTH: f32 = 320.0;
 
-    text.add(&display).font(&state.font);
+fn hud_text(app: &App, state: &State) -> String {
+    let forward = state.camera.forward();
+    let yaw = forward.x.atan2(forward.z).to_degrees();
+    let pitch = forward.y.asin().to_degrees();
+    let position = state.camera.position;
 
-    // TODO: This seems to be a bottlekneck... presumably the notan text rendering
-    // isn't intended to be used like this.
-    // IDEA: Could try to pre-render all the light values to textures and stitch
-    // them together somehow?
-    gfx.render(&text);
+    let stats = &state.renderer.stats;
+    let tests_per_ray = if stats.rays > 0 {
+        stats.tests as f32 / stats.rays as f32
+    } else {
+        0.0
+    };
+    let budget = match state.renderer.settings.ray_budget {
+        0 => String::from("no limit"),
+        budget => budget.to_string(),
+    };
 
-    println!("fps: {}", app.timer.fps().round());
+    format!(
+        "fps: {:.0} ({:.2} ms)\n\
+         camera: {} ({} layout)\n\
+         position: {:.2} {:.2} {:.2}\n\
+         yaw: {:.1} pitch: {:.1}\n\
+         rays: {} at 1/{} resolution\n\
+         secondary rays: {} of {}\n\
+         tests per ray: {:.1}\n\
+         trace {:.1} build {:.1} submit {:.1} ms\n\
+         time: {:.2}s{}",
+        app.timer.fps(),
+        app.timer.delta_f32() * 1000.0,
+        state.cameras[state.active].0,
+        state.renderer.settings.layout.name(),
+        position.x,
+        position.y,
+        position.z,
+        yaw,
+        pitch,
+        stats.rays,
+        state.renderer.settings.resolution,
+        stats.secondary,
+        budget,
+        tests_per_ray,
+        state.timings.trace.as_secs_f32() * 1000.0,
+        state.timings.build.as_secs_f32() * 1000.0,
+        state.timings.submit.as_secs_f32() * 1000.0,
+        state.time,
+        if state.paused { " (paused)" } else { "" },
+    )
 }
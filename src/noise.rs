@@ -0,0 +1,95 @@
+use crate::lighting::Rng;
+
+// Perlin's gradient noise in two dimensions, shuffled by a seed. Values are
+// roughly in [-1, 1] and zero on every integer lattice point.
+pub struct Perlin {
+    // The shuffled table twice over, so lookups never need wrapping.
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let mut table: [u8; 256] = std::array::from_fn(|i| i as u8);
+        for i in (1..table.len()).rev() {
+            let j = ((rng.next_f32() * (i + 1) as f32) as usize).min(i);
+            table.swap(i, j);
+        }
+
+        Perlin {
+            permutation: std::array::from_fn(|i| table[i % 256]),
+        }
+    }
+
+    pub fn noise(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (xi, yi) = (x0.rem_euclid(256.0) as usize, y0.rem_euclid(256.0) as usize);
+
+        let p = &self.permutation;
+        let corner = |dx: usize, dy: usize| {
+            let hash = p[p[xi + dx] as usize + yi + dy];
+            gradient(hash, fx - dx as f32, fy - dy as f32)
+        };
+
+        let (u, v) = (fade(fx), fade(fy));
+        let bottom = lerp(corner(0, 0), corner(1, 0), u);
+        let top = lerp(corner(0, 1), corner(1, 1), u);
+        lerp(bottom, top, v)
+    }
+}
+
+// The smootherstep curve, flat at both ends so cells join without creases.
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// One of eight directions picked by the hash, dotted with the offset from
+// the lattice point.
+fn gradient(hash: u8, x: f32, y: f32) -> f32 {
+    match hash & 7 {
+        0 => x + y,
+        1 => x - y,
+        2 => -x + y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+// A square grid of `resolution` by `resolution` heights from octaves of
+// noise, each twice the frequency and half the amplitude of the last, with
+// `frequency` bumps across the grid in the first. Heights are stretched to
+// fill [0, 1].
+pub fn fractal_noise(seed: u64, resolution: usize, frequency: f32, octaves: usize) -> Vec<f32> {
+    let perlin = Perlin::new(seed);
+    let scale = frequency / resolution.max(1) as f32;
+
+    let mut heights: Vec<f32> = (0..resolution * resolution)
+        .map(|i| {
+            let (x, y) = ((i % resolution) as f32, (i / resolution) as f32);
+            (0..octaves)
+                .map(|octave| {
+                    let step = 2f32.powi(octave as i32);
+                    let f = scale * step;
+                    perlin.noise(x * f, y * f) / step
+                })
+                .sum()
+        })
+        .collect();
+
+    let low = heights.iter().copied().fold(f32::INFINITY, f32::min);
+    let high = heights.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if high > low {
+        for height in &mut heights {
+            *height = (*height - low) / (high - low);
+        }
+    }
+    heights
+}
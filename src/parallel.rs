@@ -0,0 +1,44 @@
+// Rayon's parallel iterators with the `parallel` feature, or plain iterators
+// standing in for them without it, so the tracer is written once for both.
+// Only the handful of methods the tracer uses are covered.
+
+#[cfg(feature = "parallel")]
+pub use rayon::prelude::*;
+
+#[cfg(not(feature = "parallel"))]
+pub use serial::*;
+
+#[cfg(not(feature = "parallel"))]
+mod serial {
+    use std::iter::FlatMap;
+    use std::slice::Chunks;
+
+    pub trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Self::IntoIter {
+            self.into_iter()
+        }
+    }
+
+    impl<I: IntoIterator> IntoParallelIterator for I {}
+
+    pub trait ParallelSlice<T> {
+        fn par_chunks(&self, size: usize) -> Chunks<'_, T>;
+    }
+
+    impl<T> ParallelSlice<T> for [T] {
+        fn par_chunks(&self, size: usize) -> Chunks<'_, T> {
+            self.chunks(size)
+        }
+    }
+
+    pub trait ParallelIterator: Iterator + Sized {
+        fn flat_map_iter<U: IntoIterator, F: FnMut(Self::Item) -> U>(
+            self,
+            f: F,
+        ) -> FlatMap<Self, U, F> {
+            self.flat_map(f)
+        }
+    }
+
+    impl<I: Iterator> ParallelIterator for I {}
+}
@@ -0,0 +1,1327 @@
+use crate::asciicast::AsciiWriter;
+use crate::camera::{load_camera_path, Camera, Projection, Viewport, D, GRID_ASPECT};
+use crate::geometry::{
+    closest_hit, closest_hits, counting, surface_uv, Hit, RayCounts, RayPacket, ITERATIONS, LANES,
+};
+use crate::lighting::{apply_fog, area_lighting, compute_lighting, path_trace, Rng, MAX_PASSES};
+use crate::parallel::*;
+use crate::scene::{animate, simulate, Background, Object, Scene};
+use crate::settings::{DebugView, ImageFormat, RenderMode, Settings, ViewLayout};
+use notan::math::{Mat3, Vec3};
+use notan::utils::Instant;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+#[cfg(feature = "parallel")]
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+pub const WIDTH: usize = 1920;
+pub const HEIGHT: usize = 1080;
+
+pub const CELL_WIDTH: usize = 8;
+pub const CELL_HEIGHT: usize = 16;
+
+pub const ROWS: usize = HEIGHT / CELL_HEIGHT;
+pub const COLS: usize = WIDTH / CELL_WIDTH;
+
+// Cell positions (column, row) of braille dots 1 to 8, in bit order.
+pub const BRAILLE_DOTS: [(usize, usize); 8] = [
+    (0, 0),
+    (0, 1),
+    (0, 2),
+    (1, 0),
+    (1, 1),
+    (1, 2),
+    (0, 3),
+    (1, 3),
+];
+
+pub fn braille_bits(c: char) -> Option<u8> {
+    let offset = (c as u32).checked_sub(0x2800)?;
+    (offset < 0x100).then_some(offset as u8)
+}
+
+// Ordered dither thresholds for each dot, so that a cell of uniform intensity
+// lights up a proportional number of dots.
+pub const BRAILLE_THRESHOLDS: [f32; 8] = [
+    0.5 / 8.0,
+    6.5 / 8.0,
+    1.5 / 8.0,
+    4.5 / 8.0,
+    2.5 / 8.0,
+    5.5 / 8.0,
+    7.5 / 8.0,
+    3.5 / 8.0,
+];
+
+pub fn encode_braille(samples: [Option<f32>; 8], gamma: f32) -> char {
+    let mut bits = 0u32;
+
+    for (bit, sample) in samples.iter().enumerate() {
+        if let Some(i) = sample {
+            if i.clamp(0.0, 1.0).powf(gamma.recip()) > BRAILLE_THRESHOLDS[bit] {
+                bits |= 1 << bit;
+            }
+        }
+    }
+
+    char::from_u32(0x2800 + bits).unwrap()
+}
+
+// The character grid is re-traced in tiles of this many cells, and only
+// tiles whose content may have changed since the last frame are traced.
+pub const TILE_COLS: usize = 16;
+pub const TILE_ROWS: usize = 8;
+pub const TILES_X: usize = COLS.div_ceil(TILE_COLS);
+pub const TILES_Y: usize = ROWS.div_ceil(TILE_ROWS);
+
+// Cells handed to each parallel task, a multiple of the packet width.
+const CELL_BATCH: usize = LANES * 16;
+
+// The part of the grid a view is traced into, counted in cells from the
+// bottom left like the grid itself.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub column: usize,
+    pub row: usize,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl Rect {
+    pub const FULL: Rect = Rect {
+        column: 0,
+        row: 0,
+        columns: COLS,
+        rows: ROWS,
+    };
+
+    pub fn contains(&self, i: usize) -> bool {
+        let (column, row) = (i % COLS, i / COLS);
+        (self.column..self.column + self.columns).contains(&column)
+            && (self.row..self.row + self.rows).contains(&row)
+    }
+
+    // Indices into the grid of every cell inside, row by row.
+    pub fn cells(self) -> impl Iterator<Item = usize> {
+        (self.row..self.row + self.rows).flat_map(move |row| {
+            (self.column..self.column + self.columns).map(move |column| row * COLS + column)
+        })
+    }
+
+    // Where cell `i` lies from the middle of the rectangle, which is where
+    // the camera looks.
+    fn offset(&self, i: usize) -> (f32, f32) {
+        (
+            (i % COLS - self.column) as f32 - (self.columns / 2) as f32,
+            (i / COLS - self.row) as f32 - (self.rows / 2) as f32,
+        )
+    }
+
+    // Width over height on screen, which a camera drawn into it needs to
+    // keep circles round.
+    pub fn aspect(&self) -> f32 {
+        GRID_ASPECT * (self.columns as f32 / COLS as f32) / (self.rows as f32 / ROWS as f32)
+    }
+}
+
+// Where the active camera's view goes on the grid and where the second
+// camera's does, if it's shown. The inset is a third of the grid each way,
+// a cell in from the top right corner.
+pub fn layout_rects(layout: ViewLayout) -> (Rect, Option<Rect>) {
+    match layout {
+        ViewLayout::Single => (Rect::FULL, None),
+        ViewLayout::Inset => {
+            let (columns, rows) = (COLS / 3, ROWS / 3);
+            let inset = Rect {
+                column: COLS - columns - 1,
+                row: ROWS - rows - 1,
+                columns,
+                rows,
+            };
+            (Rect::FULL, Some(inset))
+        }
+        ViewLayout::Split => {
+            let half = COLS / 2;
+            let left = Rect {
+                columns: half,
+                ..Rect::FULL
+            };
+            let right = Rect {
+                column: half,
+                columns: COLS - half,
+                ..Rect::FULL
+            };
+            (left, Some(right))
+        }
+    }
+}
+
+// How far the camera may drift from the last full trace while the old frame
+// is reprojected instead of traced again.
+pub const REPROJECT_DISTANCE: f32 = 0.5;
+
+// The character grid, bottom row first, the radiance each cell's character
+// was picked from, and the distance along each cell's ray to the surface
+// shown there.
+#[derive(Clone)]
+pub struct FrameBuffer {
+    pub cells: Vec<char>,
+    pub radiance: Vec<Vec3>,
+    pub depth: Vec<f32>,
+}
+
+impl FrameBuffer {
+    fn new() -> Self {
+        FrameBuffer {
+            cells: vec![' '; COLS * ROWS],
+            radiance: vec![Vec3::ZERO; COLS * ROWS],
+            depth: vec![f32::INFINITY; COLS * ROWS],
+        }
+    }
+}
+
+// A fully traced frame that later frames can be reprojected from.
+struct Anchor {
+    position: Vec3,
+    rotation: Mat3,
+    frame: FrameBuffer,
+}
+
+pub struct FrameCache {
+    dirty: Vec<bool>,
+    view: Option<(Vec3, Mat3)>,
+    anchor: Option<Anchor>,
+    // Whether the frame is reprojected rather than traced.
+    reprojected: bool,
+    // Path traced radiance summed over `passes` frames, and whether any pass
+    // hit something in each cell.
+    accumulation: Vec<Vec3>,
+    coverage: Vec<bool>,
+    passes: u32,
+}
+
+impl FrameCache {
+    fn new() -> Self {
+        FrameCache {
+            dirty: vec![true; TILES_X * TILES_Y],
+            view: None,
+            anchor: None,
+            reprojected: false,
+            accumulation: vec![Vec3::ZERO; COLS * ROWS],
+            coverage: vec![false; COLS * ROWS],
+            passes: 0,
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.dirty.fill(true);
+        self.anchor = None;
+    }
+
+    // Marks the tiles covering an object as it appears from `camera`. Call it
+    // both before and after editing an object so the old and new positions
+    // are traced again.
+    pub fn invalidate_object(&mut self, camera: &Camera, object: &Object) {
+        let Some((center, radius)) = object.bounds() else {
+            self.invalidate();
+            return;
+        };
+        if camera.projection != Projection::Perspective {
+            self.invalidate();
+            return;
+        }
+        self.anchor = None;
+
+        // Project the corners of the sphere's bounding box in camera space,
+        // which gives a conservative rectangle of cells.
+        let local = camera.rotation.transpose() * (center - camera.position);
+        if local.z - radius < D {
+            self.invalidate();
+            return;
+        }
+
+        let (mut min_x, mut max_x) = (f32::INFINITY, f32::NEG_INFINITY);
+        let (mut min_y, mut max_y) = (f32::INFINITY, f32::NEG_INFINITY);
+        for z in [local.z - radius, local.z + radius] {
+            for dx in [-radius, radius] {
+                let x = (local.x + dx) * D / z * COLS as f32 / camera.viewport.width;
+                min_x = min_x.min(x);
+                max_x = max_x.max(x);
+            }
+            for dy in [-radius, radius] {
+                let y = (local.y + dy) * D / z * ROWS as f32 / camera.viewport.height;
+                min_y = min_y.min(y);
+                max_y = max_y.max(y);
+            }
+        }
+
+        // Convert to grid indices, with a cell of margin for sub-cell samples.
+        let to_tile = |v: f32, half: usize, cells: usize, tile: usize, tiles: usize| {
+            ((v + half as f32).clamp(0.0, (cells - 1) as f32) as usize / tile).min(tiles - 1)
+        };
+        let tx0 = to_tile(min_x - 1.0, COLS / 2, COLS, TILE_COLS, TILES_X);
+        let tx1 = to_tile(max_x + 1.0, COLS / 2, COLS, TILE_COLS, TILES_X);
+        let ty0 = to_tile(min_y - 1.0, ROWS / 2, ROWS, TILE_ROWS, TILES_Y);
+        let ty1 = to_tile(max_y + 1.0, ROWS / 2, ROWS, TILE_ROWS, TILES_Y);
+
+        for ty in ty0..=ty1 {
+            for tx in tx0..=tx1 {
+                self.dirty[ty * TILES_X + tx] = true;
+            }
+        }
+    }
+
+    // Takes over tiles marked dirty elsewhere. Any damage at all means the
+    // anchor no longer matches the scene.
+    fn absorb(&mut self, damage: &[bool]) {
+        if damage.iter().any(|d| *d) {
+            self.anchor = None;
+        }
+        for (dirty, damaged) in self.dirty.iter_mut().zip(damage) {
+            *dirty |= damaged;
+        }
+    }
+
+    fn tile_of(i: usize) -> usize {
+        i / COLS / TILE_ROWS * TILES_X + i % COLS / TILE_COLS
+    }
+
+    // Splats the anchor frame into the current view, returning the new
+    // frame and a mask of the cells nothing landed in. Only translations
+    // are handled, so cells that missed everything stay where they were.
+    fn reproject(&self, camera: &Camera) -> Option<(FrameBuffer, Vec<bool>)> {
+        let anchor = self.anchor.as_ref()?;
+        if camera.projection != Projection::Perspective
+            || anchor.rotation != camera.rotation
+            || anchor.position.distance(camera.position) > REPROJECT_DISTANCE
+        {
+            return None;
+        }
+
+        let source = Camera {
+            position: anchor.position,
+            rotation: anchor.rotation,
+            viewport: Viewport {
+                width: camera.viewport.width,
+                height: camera.viewport.height,
+            },
+            projection: Projection::Perspective,
+            aperture: 0.0,
+            focus_distance: camera.focus_distance,
+            aspect: camera.aspect,
+        };
+
+        let mut buffer = vec![' '; COLS * ROWS];
+        let mut radiance = vec![Vec3::ZERO; COLS * ROWS];
+        let mut depth = vec![f32::INFINITY; COLS * ROWS];
+        let mut holes = vec![true; COLS * ROWS];
+
+        let source_frame = &anchor.frame;
+        for (i, ((&c, &r), &t)) in source_frame
+            .cells
+            .iter()
+            .zip(&source_frame.radiance)
+            .zip(&source_frame.depth)
+            .enumerate()
+        {
+            let target = if t.is_finite() {
+                let x = (i % COLS) as f32 - (COLS / 2) as f32;
+                let y = (i / COLS) as f32 - (ROWS / 2) as f32;
+                let point = source.position
+                    + source.rotation * source.camera_pixel_to_viewport_distance(x, y) * t;
+
+                let Some((x, y, t)) = camera.project(point) else {
+                    continue;
+                };
+                let column = x.round() + (COLS / 2) as f32;
+                let row = y.round() + (ROWS / 2) as f32;
+                if column < 0.0 || column >= COLS as f32 || row < 0.0 || row >= ROWS as f32 {
+                    continue;
+                }
+                (row as usize * COLS + column as usize, t)
+            } else {
+                (i, t)
+            };
+
+            let (j, t) = target;
+            if holes[j] || t < depth[j] {
+                buffer[j] = c;
+                radiance[j] = r;
+                depth[j] = t;
+                holes[j] = false;
+            }
+        }
+
+        Some((
+            FrameBuffer {
+                cells: buffer,
+                radiance,
+                depth,
+            },
+            holes,
+        ))
+    }
+}
+
+// Ray counts from the most recent trace, shown on the HUD, and how many cells
+// or blocks of cells were traced for them. Secondary rays are the shadow and
+// bounce rays of path tracing.
+#[derive(Default)]
+pub struct TraceStats {
+    pub rays: usize,
+    pub tests: usize,
+    pub cells: usize,
+    pub secondary: usize,
+}
+
+// Scales of the depth and iteration count debug views: anything further or
+// costlier is drawn the same.
+pub const DEPTH_RANGE: f32 = 20.0;
+pub const ITERATION_RANGE: f32 = 1024.0;
+
+pub fn intensity_to_char(i: f32, ramp: &[char], gamma: f32) -> char {
+    let i = i.clamp(0.0, 1.0).powf(gamma.recip());
+    let index = (i * ramp.len() as f32) as usize;
+    ramp[index.min(ramp.len() - 1)]
+}
+
+// Tone mapping, the last step between the tracer and what's shown. Shading
+// works in RGB radiance throughout; only here is it reduced to the single
+// brightness a character or braille dot can show, or clamped into the bytes
+// of a pixel.
+const LUMA: Vec3 = Vec3::new(0.2126, 0.7152, 0.0722);
+
+pub fn luminance(radiance: Vec3) -> f32 {
+    radiance.dot(LUMA)
+}
+
+pub fn radiance_to_char(radiance: Vec3, ramp: &[char], gamma: f32) -> char {
+    intensity_to_char(luminance(radiance), ramp, gamma)
+}
+
+pub fn radiance_to_rgb(radiance: Vec3) -> [u8; 3] {
+    let rgb = radiance.clamp(Vec3::ZERO, Vec3::ONE) * 255.0;
+    [rgb.x, rgb.y, rgb.z].map(|c| c.round() as u8)
+}
+
+// What a ray from the camera sees: the fogged radiance of the closest hit and
+// its distance, or the background at an infinite distance. None only when the
+// ray misses and there is no background.
+pub fn trace_view(
+    origin: Vec3,
+    direction: Vec3,
+    objects: &[Object],
+    background: Option<&Background>,
+    settings: &Settings,
+) -> Option<(Vec3, f32)> {
+    ITERATIONS.set(0);
+    let hit = closest_hit(origin, direction, 1.0, f32::INFINITY, objects);
+
+    if settings.debug_view != DebugView::Shaded {
+        return debug_view(origin, direction, hit, objects, ITERATIONS.get(), settings);
+    }
+    shade_view(origin, direction, hit, objects, background, settings)
+}
+
+// trace_view for a batch of rays, traced a packet at a time with any left
+// over traced singly. Debug views are traced singly throughout, so that
+// iteration counts belong to one ray.
+pub fn trace_views(
+    rays: &[(Vec3, Vec3)],
+    objects: &[Object],
+    background: Option<&Background>,
+    settings: &Settings,
+) -> Vec<Option<(Vec3, f32)>> {
+    if settings.debug_view != DebugView::Shaded {
+        return rays
+            .iter()
+            .map(|&(origin, direction)| {
+                trace_view(origin, direction, objects, background, settings)
+            })
+            .collect();
+    }
+
+    let mut samples = Vec::with_capacity(rays.len());
+
+    let mut packets = rays.chunks_exact(LANES);
+    for rays in &mut packets {
+        let rays: &[(Vec3, Vec3); LANES] = rays.try_into().unwrap();
+        let hits = closest_hits(&RayPacket::new(rays), 1.0, f32::INFINITY, objects);
+
+        for (&(origin, direction), hit) in rays.iter().zip(hits) {
+            samples.push(shade_view(
+                origin, direction, hit, objects, background, settings,
+            ));
+        }
+    }
+
+    for &(origin, direction) in packets.remainder() {
+        samples.push(trace_view(origin, direction, objects, background, settings));
+    }
+
+    samples
+}
+
+fn shade_view(
+    origin: Vec3,
+    direction: Vec3,
+    hit: Option<(usize, Hit)>,
+    objects: &[Object],
+    background: Option<&Background>,
+    settings: &Settings,
+) -> Option<(Vec3, f32)> {
+    let backdrop = background.map_or(Vec3::ZERO, |b| b.sample(direction));
+
+    let Some((index, hit)) = hit else {
+        return background.map(|_| (backdrop, f32::INFINITY));
+    };
+
+    let p = origin + hit.t * direction;
+    let normal = hit.normal.normalize();
+    let i = compute_lighting(p, normal, hit.albedo, origin, &settings.lighting)
+        + settings.lighting.diffuse * hit.albedo * area_lighting(p, normal, index, objects)
+        + objects[index].emission();
+    Some((
+        apply_fog(i, backdrop, hit.t * direction.length(), settings.fog()),
+        hit.t,
+    ))
+}
+
+// What a debug view shows for a ray in place of its lit radiance, as a grey
+// level. Misses are left empty, except in the views about misses and cost.
+fn debug_view(
+    origin: Vec3,
+    direction: Vec3,
+    hit: Option<(usize, Hit)>,
+    objects: &[Object],
+    iterations: usize,
+    settings: &Settings,
+) -> Option<(Vec3, f32)> {
+    let t = hit.map_or(f32::INFINITY, |(_, hit)| hit.t);
+
+    let value = match (settings.debug_view, hit) {
+        (DebugView::Shaded, _) => unreachable!("shaded rays are lit by shade_view"),
+        (DebugView::Hits, hit) => {
+            if hit.is_some() {
+                1.0
+            } else {
+                0.0
+            }
+        }
+        (DebugView::Iterations, _) => (iterations as f32).ln_1p() / ITERATION_RANGE.ln_1p(),
+        (_, None) => return None,
+        (DebugView::Depth, _) => 1.0 - (t / DEPTH_RANGE).min(1.0),
+        (DebugView::NormalX | DebugView::NormalY | DebugView::NormalZ, Some((_, hit))) => {
+            let normal = hit.normal.normalize();
+            let component = match settings.debug_view {
+                DebugView::NormalX => normal.x,
+                DebugView::NormalY => normal.y,
+                _ => normal.z,
+            };
+            0.5 + 0.5 * component
+        }
+        (DebugView::U | DebugView::V, Some((i, _))) => {
+            match surface_uv(&objects[i], origin, direction, t) {
+                Some((u, _)) if settings.debug_view == DebugView::U => u,
+                Some((_, v)) => v,
+                None => 0.0,
+            }
+        }
+        // Stepping by the golden ratio keeps neighbouring indices far apart.
+        (DebugView::Object, Some((i, _))) => ((i + 1) as f32 * 0.618_034).fract(),
+    };
+
+    Some((Vec3::splat(value), t))
+}
+
+// Radical inverse of `index` in `base`, used to scatter sub-cell samples
+// evenly without the frame to frame noise of random jitter.
+pub fn halton(mut index: usize, base: usize) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
+
+// The `index`th point on the lens for a pixel. Each pixel shifts the shared
+// sequence by its own random offset so neighbouring pixels don't blur in
+// lockstep, which shows up as streaks.
+pub fn lens_sample(index: usize, seed: u64) -> (f32, f32) {
+    let mut rng = Rng::new(seed);
+    (
+        (halton(index, 5) + rng.next_f32()).fract(),
+        (halton(index, 7) + rng.next_f32()).fract(),
+    )
+}
+
+// The jittered rays averaged across the cell at (x, y) of a view filling
+// `rect`.
+fn cell_rays(
+    camera: &Camera,
+    rect: Rect,
+    x: f32,
+    y: f32,
+    settings: &Settings,
+) -> Vec<(Vec3, Vec3)> {
+    (0..settings.samples)
+        .map(|sample| {
+            let (jitter_x, jitter_y) = if settings.samples == 1 {
+                (0.0, 0.0)
+            } else {
+                (halton(sample + 1, 2) - 0.5, halton(sample + 1, 3) - 0.5)
+            };
+
+            camera.canvas_ray(
+                x + jitter_x,
+                y + jitter_y,
+                rect.columns as f32,
+                rect.rows as f32,
+                lens_sample(sample + 1, (x as i64 as u64) << 32 ^ y as i64 as u64),
+            )
+        })
+        .collect()
+}
+
+// Averages the samples of a cell. Rays that miss count as black, so edges
+// fade into the background instead of shimmering; the cell is only left
+// empty when every ray misses. Also returns the nearest distance seen, for
+// reprojection.
+fn shade_cell(samples: &[Option<(Vec3, f32)>], settings: &Settings) -> (char, Vec3, f32) {
+    let mut total = Vec3::ZERO;
+    let mut hits = 0;
+    let mut depth = f32::INFINITY;
+
+    for (i, t) in samples.iter().flatten() {
+        total += *i;
+        hits += 1;
+        depth = depth.min(*t);
+    }
+
+    if hits == 0 {
+        return (' ', Vec3::ZERO, depth);
+    }
+
+    let radiance = total / samples.len() as f32;
+    let c = radiance_to_char(radiance, settings.ramp.chars(), settings.gamma);
+    (c, radiance, depth)
+}
+
+// Rays through the cell at (x, y) on a 2x4 grid matching the braille dot
+// layout, top row first, since cell coordinates grow upwards.
+fn braille_rays(camera: &Camera, rect: Rect, x: f32, y: f32) -> Vec<(Vec3, Vec3)> {
+    BRAILLE_DOTS
+        .iter()
+        .map(|&(column, row)| {
+            let sample_x = x - 0.5 + (column as f32 + 0.5) / 2.0;
+            let sample_y = y + 0.5 - (row as f32 + 0.5) / 4.0;
+
+            let dot = column * 4 + row + 1;
+            camera.canvas_ray(
+                sample_x,
+                sample_y,
+                rect.columns as f32,
+                rect.rows as f32,
+                lens_sample(dot, (x as i64 as u64) << 32 ^ y as i64 as u64),
+            )
+        })
+        .collect()
+}
+
+// Each dot is lit from its own ray, and the cell keeps the average radiance
+// of the dots that hit.
+fn shade_braille(samples: &[Option<(Vec3, f32)>], settings: &Settings) -> (char, Vec3, f32) {
+    let depth = samples
+        .iter()
+        .flatten()
+        .map(|(_, t)| *t)
+        .fold(f32::INFINITY, f32::min);
+    let hits: Vec<Vec3> = samples.iter().flatten().map(|(r, _)| *r).collect();
+    let radiance = if hits.is_empty() {
+        Vec3::ZERO
+    } else {
+        hits.iter().sum::<Vec3>() / hits.len() as f32
+    };
+
+    let dots: [Option<f32>; 8] = std::array::from_fn(|dot| samples[dot].map(|(r, _)| luminance(r)));
+    (encode_braille(dots, settings.gamma), radiance, depth)
+}
+
+// Traces frames of the character grid, keeping what it needs from one frame
+// to the next to trace only what changed. Only the cells inside `rect` are
+// traced, with the camera's view fitted to them.
+pub struct Renderer {
+    pub settings: Settings,
+    pub cache: FrameCache,
+    pub stats: TraceStats,
+    pub frame: FrameBuffer,
+    pub rect: Rect,
+}
+
+impl Renderer {
+    pub fn new(settings: Settings) -> Self {
+        Renderer {
+            settings,
+            cache: FrameCache::new(),
+            stats: TraceStats::default(),
+            frame: FrameBuffer::new(),
+            rect: Rect::FULL,
+        }
+    }
+
+    // Moves the view to another part of the grid, starting it over since
+    // nothing traced for the old one lines up.
+    pub fn set_rect(&mut self, rect: Rect) {
+        if self.rect != rect {
+            self.rect = rect;
+            self.cache.invalidate();
+        }
+    }
+
+    pub fn render_frame(&mut self, scene: &Scene, camera: &Camera) -> &FrameBuffer {
+        // A view in part of the grid is reshaped to fit it, as images are,
+        // unless the aspect was set by hand.
+        let mut camera = camera.clone();
+        if self.rect != Rect::FULL && self.settings.aspect.is_none() {
+            camera.set_aspect(self.rect.aspect());
+        }
+
+        if self.settings.path_traced() {
+            self.render_progressive(scene, &camera);
+        } else {
+            self.render_cells(scene, &camera);
+        }
+
+        &self.frame
+    }
+
+    fn render_cells(&mut self, scene: &Scene, camera: &Camera) {
+        // Reproject while the camera is moving, then trace a clean frame once
+        // it comes to rest.
+        let view = (camera.position, camera.rotation);
+        let mut retrace = vec![false; COLS * ROWS];
+        if self.cache.view != Some(view) {
+            // Reprojection works in whole grid coordinates.
+            let reprojected = if self.settings.reproject && self.rect == Rect::FULL {
+                self.cache.reproject(camera)
+            } else {
+                None
+            };
+
+            match reprojected {
+                Some((frame, holes)) => {
+                    self.frame = frame;
+                    retrace = holes;
+                    self.cache.reprojected = true;
+                }
+                None => self.cache.invalidate(),
+            }
+            self.cache.view = Some(view);
+        } else if self.cache.reprojected {
+            self.cache.invalidate();
+        }
+
+        let full = self.cache.dirty.iter().all(|d| *d);
+        if full {
+            self.cache.reprojected = false;
+        }
+        for (i, r) in retrace.iter_mut().enumerate() {
+            *r |= self.cache.dirty[FrameCache::tile_of(i)];
+        }
+
+        let objects = &scene.objects;
+        let background = scene.background.as_ref();
+        let settings = &self.settings;
+
+        // Below full resolution each block is traced through its middle from
+        // its first cell, if any cell in it needs tracing. Blocks are counted
+        // from the corner of the view and end at its edges.
+        let rect = self.rect;
+        let resolution = settings.resolution;
+        let offset = (resolution - 1) as f32 / 2.0;
+        let block = |i: usize| {
+            let (column, row) = (i % COLS, i / COLS);
+            let (right, top) = (rect.column + rect.columns, rect.row + rect.rows);
+            (row..(row + resolution).min(top)).flat_map(move |row| {
+                (column..(column + resolution).min(right)).map(move |column| row * COLS + column)
+            })
+        };
+
+        // Runs of neighbouring cells are traced together so that even with
+        // one ray per cell the rays fill whole packets.
+        let cells: Vec<usize> = rect
+            .cells()
+            .filter(|i| {
+                (i % COLS - rect.column).is_multiple_of(resolution)
+                    && (i / COLS - rect.row).is_multiple_of(resolution)
+            })
+            .filter(|i| block(*i).any(|j| retrace[j]))
+            .collect();
+        let traced: Vec<_> = cells
+            .par_chunks(CELL_BATCH)
+            .map(|chunk| {
+                counting(|| {
+                    let rays: Vec<(Vec3, Vec3)> = chunk
+                        .iter()
+                        .flat_map(|&i| {
+                            let (x, y) = rect.offset(i);
+                            let (x, y) = (x + offset, y + offset);
+
+                            match settings.mode {
+                                RenderMode::Ramp => cell_rays(camera, rect, x, y, settings),
+                                RenderMode::Braille => braille_rays(camera, rect, x, y),
+                            }
+                        })
+                        .collect();
+
+                    let samples = trace_views(&rays, objects, background, settings);
+                    let per_cell = samples.len() / chunk.len();
+
+                    chunk
+                        .iter()
+                        .zip(samples.chunks(per_cell))
+                        .map(|(&i, samples)| {
+                            let cell = match settings.mode {
+                                RenderMode::Ramp => shade_cell(samples, settings),
+                                RenderMode::Braille => shade_braille(samples, settings),
+                            };
+                            (i, cell)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let mut counts = RayCounts::default();
+        let mut count = 0;
+        for (batch, batch_counts) in traced {
+            counts += batch_counts;
+            count += batch.len();
+            for (i, (c, radiance, t)) in batch {
+                for j in block(i) {
+                    self.frame.cells[j] = c;
+                    self.frame.radiance[j] = radiance;
+                    self.frame.depth[j] = t;
+                }
+            }
+        }
+        self.cache.dirty.fill(false);
+
+        self.stats = TraceStats {
+            rays: counts.rays,
+            tests: counts.tests,
+            cells: count,
+            secondary: counts.secondary,
+        };
+
+        if full {
+            self.cache.anchor = Some(Anchor {
+                position: camera.position,
+                rotation: camera.rotation,
+                frame: self.frame.clone(),
+            });
+        }
+    }
+
+    // Adds one path traced pass over the whole grid to the accumulation
+    // buffer, starting over whenever anything on screen changes.
+    fn render_progressive(&mut self, scene: &Scene, camera: &Camera) {
+        let view = (camera.position, camera.rotation);
+        if self.cache.view != Some(view) {
+            self.cache.invalidate();
+            self.cache.view = Some(view);
+        }
+
+        let cache = &mut self.cache;
+        if cache.dirty.iter().any(|d| *d) {
+            cache.accumulation.fill(Vec3::ZERO);
+            cache.coverage.fill(false);
+            self.frame.depth.fill(f32::INFINITY);
+            cache.passes = 0;
+            cache.dirty.fill(false);
+        }
+
+        if cache.passes >= MAX_PASSES {
+            self.stats = TraceStats::default();
+            return;
+        }
+
+        let pass = cache.passes;
+        let objects = &scene.objects;
+        let background = scene.background.as_ref();
+        let settings = &self.settings;
+        let samples = settings.samples;
+        let fog = settings.fog();
+
+        // The budget is shared out evenly between the paths, rounding each
+        // share up or down at random so none of it is lost to rounding.
+        let rect = self.rect;
+        let cells: Vec<usize> = rect.cells().collect();
+        let share = settings.ray_budget as f32 / (cells.len() * samples) as f32;
+
+        let traced: Vec<(Option<(Vec3, f32)>, RayCounts)> = (&cells)
+            .into_par_iter()
+            .map(|&i| {
+                counting(|| {
+                    let mut rng = Rng::new((pass as u64) << 32 | i as u64);
+                    let (x, y) = rect.offset(i);
+
+                    let mut total = None;
+                    let mut depth = f32::INFINITY;
+                    for _ in 0..samples {
+                        let (jitter_x, jitter_y) = (rng.next_f32() - 0.5, rng.next_f32() - 0.5);
+                        let (origin, direction) = camera.canvas_ray(
+                            x + jitter_x,
+                            y + jitter_y,
+                            rect.columns as f32,
+                            rect.rows as f32,
+                            (rng.next_f32(), rng.next_f32()),
+                        );
+
+                        let budget =
+                            (settings.ray_budget > 0).then(|| (share + rng.next_f32()) as usize);
+                        if let Some((radiance, t)) = path_trace(
+                            origin, direction, objects, background, settings, &mut rng, budget,
+                        ) {
+                            let backdrop = background.map_or(Vec3::ZERO, |b| b.sample(direction));
+                            *total.get_or_insert(Vec3::ZERO) +=
+                                apply_fog(radiance, backdrop, t * direction.length(), fog);
+                            depth = depth.min(t);
+                        }
+                    }
+                    total.map(|total: Vec3| (total / samples as f32, depth))
+                })
+            })
+            .collect();
+
+        cache.passes += 1;
+        let ramp = settings.ramp.chars();
+        let mut counts = RayCounts::default();
+        for (&i, (sample, cell_counts)) in cells.iter().zip(traced) {
+            counts += cell_counts;
+            if let Some((radiance, depth)) = sample {
+                cache.accumulation[i] += radiance;
+                cache.coverage[i] = true;
+                self.frame.depth[i] = self.frame.depth[i].min(depth);
+            }
+
+            (self.frame.cells[i], self.frame.radiance[i]) = if cache.coverage[i] {
+                let radiance = cache.accumulation[i] / cache.passes as f32;
+                (radiance_to_char(radiance, ramp, settings.gamma), radiance)
+            } else {
+                (' ', Vec3::ZERO)
+            };
+        }
+
+        self.stats = TraceStats {
+            rays: counts.rays,
+            tests: counts.tests,
+            cells: cells.len(),
+            secondary: counts.secondary,
+        };
+    }
+}
+
+// A frame for the worker to trace, with copies of everything it reads so the
+// front-end can carry on changing its own. `second` is the camera shown
+// alongside the active one, when the layout has room for it.
+struct Job {
+    scene: Scene,
+    camera: Camera,
+    second: Option<Camera>,
+    settings: Settings,
+    damage: Vec<bool>,
+}
+
+struct Traced {
+    frame: FrameBuffer,
+    stats: TraceStats,
+    time: Duration,
+}
+
+impl Job {
+    // The second view has a renderer of its own, so each keeps its own cache
+    // and the two are composed into one frame afterwards. Damage is marked
+    // as the active camera sees it over the whole grid, so any at all starts
+    // over a view that's been moved or reshaped to fit beside another.
+    fn trace(self, renderer: &mut Renderer, second: &mut Renderer) -> Traced {
+        let (rect, second_rect) = match self.second {
+            Some(_) => layout_rects(self.settings.layout),
+            None => (Rect::FULL, None),
+        };
+        let damaged = self.damage.iter().any(|d| *d);
+        renderer.set_rect(rect);
+        renderer.settings = self.settings.clone();
+        if rect != Rect::FULL && damaged {
+            renderer.cache.invalidate();
+        } else {
+            renderer.cache.absorb(&self.damage);
+        }
+
+        let start = Instant::now();
+        renderer.render_frame(&self.scene, &self.camera);
+        let mut frame = renderer.frame.clone();
+        let mut stats = std::mem::take(&mut renderer.stats);
+
+        if let (Some(camera), Some(rect)) = (&self.second, second_rect) {
+            second.set_rect(rect);
+            second.settings = self.settings;
+            if damaged {
+                second.cache.invalidate();
+            }
+            second.render_frame(&self.scene, camera);
+
+            for i in rect.cells() {
+                frame.cells[i] = second.frame.cells[i];
+                frame.radiance[i] = second.frame.radiance[i];
+                frame.depth[i] = second.frame.depth[i];
+            }
+            stats.rays += second.stats.rays;
+            stats.tests += second.stats.tests;
+            stats.cells += second.stats.cells;
+            stats.secondary += second.stats.secondary;
+        }
+
+        Traced {
+            frame,
+            stats,
+            time: start.elapsed(),
+        }
+    }
+}
+
+// Traces frames on a thread of its own, so input and drawing carry on while a
+// slow frame is in flight. One frame is traced at a time, from the scene as
+// it was when the previous one finished; anything that changed in between is
+// only ever seen in its latest state. Without the `parallel` feature there
+// are no threads, and each frame is traced as soon as it is requested.
+pub struct RenderWorker {
+    pub settings: Settings,
+    // Records damage the same way as the renderer's cache, and hands it over
+    // with the next job.
+    pub cache: FrameCache,
+    // The most recently finished frame, shown until the next one arrives.
+    pub frame: FrameBuffer,
+    pub stats: TraceStats,
+    pub trace_time: Duration,
+    busy: bool,
+    #[cfg(feature = "parallel")]
+    jobs: Sender<Job>,
+    #[cfg(feature = "parallel")]
+    traced: Receiver<Traced>,
+    #[cfg(not(feature = "parallel"))]
+    renderers: (Renderer, Renderer),
+    #[cfg(not(feature = "parallel"))]
+    traced: Option<Traced>,
+}
+
+impl RenderWorker {
+    pub fn new(settings: Settings) -> Self {
+        #[cfg(feature = "parallel")]
+        let (jobs, traced) = {
+            let (jobs, incoming) = mpsc::channel::<Job>();
+            let (outgoing, traced) = mpsc::channel();
+
+            let mut renderer = Renderer::new(settings.clone());
+            let mut second = Renderer::new(settings.clone());
+            std::thread::spawn(move || {
+                for job in incoming {
+                    if outgoing
+                        .send(job.trace(&mut renderer, &mut second))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+            (jobs, traced)
+        };
+
+        let mut cache = FrameCache::new();
+        cache.dirty.fill(false);
+
+        RenderWorker {
+            cache,
+            frame: FrameBuffer::new(),
+            stats: TraceStats::default(),
+            trace_time: Duration::ZERO,
+            busy: false,
+            #[cfg(feature = "parallel")]
+            jobs,
+            #[cfg(feature = "parallel")]
+            traced,
+            #[cfg(not(feature = "parallel"))]
+            renderers: (
+                Renderer::new(settings.clone()),
+                Renderer::new(settings.clone()),
+            ),
+            #[cfg(not(feature = "parallel"))]
+            traced: None,
+            settings,
+        }
+    }
+
+    // Picks up the frame in flight if it has finished, returning whether it
+    // had.
+    pub fn receive(&mut self) -> bool {
+        #[cfg(feature = "parallel")]
+        let Ok(traced) = self.traced.try_recv() else {
+            return false;
+        };
+        #[cfg(not(feature = "parallel"))]
+        let Some(traced) = self.traced.take() else {
+            return false;
+        };
+
+        self.frame = traced.frame;
+        self.stats = traced.stats;
+        self.trace_time = traced.time;
+        self.busy = false;
+        true
+    }
+
+    // Starts tracing the scene as it is now, unless a frame is already in
+    // flight. Meant to be called on every update.
+    pub fn request(&mut self, scene: &Scene, camera: &Camera, second: Option<&Camera>) {
+        if self.busy {
+            return;
+        }
+
+        let job = Job {
+            scene: scene.clone(),
+            camera: camera.clone(),
+            second: second.cloned(),
+            settings: self.settings.clone(),
+            damage: std::mem::replace(&mut self.cache.dirty, vec![false; TILES_X * TILES_Y]),
+        };
+        #[cfg(feature = "parallel")]
+        {
+            self.busy = self.jobs.send(job).is_ok();
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            let (renderer, second) = &mut self.renderers;
+            self.traced = Some(job.trace(renderer, second));
+            self.busy = true;
+        }
+    }
+}
+
+// Traces the camera view into an RGB image, averaging a grid of
+// samples x samples rays over each pixel.
+pub fn render_image(scene: &Scene, camera: &Camera, settings: &Settings) -> Vec<u8> {
+    let objects = &scene.objects;
+    let background = scene.background.as_ref();
+    let (width, height) = (settings.image_width, settings.image_height);
+    let samples = settings.image_samples;
+
+    // Pixels are square, unlike cells, so the viewport is reshaped to the
+    // image unless the aspect was set by hand.
+    let mut camera = camera.clone();
+    if settings.aspect.is_none() {
+        camera.set_aspect(width as f32 / height as f32);
+    }
+    let camera = &camera;
+
+    // Rows are traced in parallel, with the rays of each row in packets.
+    (0..height)
+        .into_par_iter()
+        .flat_map_iter(|row| {
+            let rays: Vec<(Vec3, Vec3)> = (row * width..(row + 1) * width)
+                .flat_map(|i| {
+                    (0..samples * samples).map(move |sample| {
+                        let (sx, sy) = (sample % samples, sample / samples);
+                        let x = (i % width) as f32 + (sx as f32 + 0.5) / samples as f32;
+                        let y = (i / width) as f32 + (sy as f32 + 0.5) / samples as f32;
+
+                        camera.canvas_ray(
+                            x - width as f32 / 2.0,
+                            height as f32 / 2.0 - y,
+                            width as f32,
+                            height as f32,
+                            lens_sample(sample + 1, i as u64),
+                        )
+                    })
+                })
+                .collect();
+
+            trace_views(&rays, objects, background, settings)
+                .chunks(samples * samples)
+                .flat_map(|pixel| {
+                    let total: Vec3 = pixel.iter().flatten().map(|(r, _)| r).sum();
+                    radiance_to_rgb(total / pixel.len() as f32)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+pub fn write_image(
+    path: &str,
+    format: ImageFormat,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) -> Result<(), String> {
+    match format {
+        ImageFormat::Png => write_png(path, width, height, pixels),
+        ImageFormat::Ppm => write_ppm(path, width, height, pixels),
+    }
+}
+
+pub fn image_extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "png",
+        ImageFormat::Ppm => "ppm",
+    }
+}
+
+pub fn write_png(path: &str, width: usize, height: usize, pixels: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(pixels).map_err(|e| e.to_string())
+}
+
+pub fn write_ppm(path: &str, width: usize, height: usize, pixels: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+
+    let mut writer = BufWriter::new(file);
+    write!(writer, "P6\n{width} {height}\n255\n").map_err(|e| e.to_string())?;
+    writer.write_all(pixels).map_err(|e| e.to_string())
+}
+
+// Renders `settings.frames` images along the camera path in the file at
+// `path` into `settings.output`, animating the scene as it goes, and the
+// character frames to `settings.ascii` if it's set.
+pub fn render_animation(settings: &Settings, path: &str, scene: &mut Scene) -> Result<(), String> {
+    let camera_path = load_camera_path(path)?;
+    let (start, end) = camera_path.duration();
+
+    std::fs::create_dir_all(&settings.output).map_err(|e| e.to_string())?;
+
+    // Character frames, if they're wanted too, are timed from the start of
+    // the path.
+    let mut ascii = settings
+        .ascii
+        .as_deref()
+        .map(|path| AsciiWriter::create(path, settings.ascii_format))
+        .transpose()?;
+    let mut renderer = Renderer::new(settings.clone());
+
+    // Objects start at their scene positions at time zero.
+    let mut scene_time = 0.0;
+
+    let mut camera = Camera::new(settings);
+    for frame in 0..settings.frames {
+        let f = if settings.frames > 1 {
+            frame as f32 / (settings.frames - 1) as f32
+        } else {
+            0.0
+        };
+        let time = start + (end - start) * f;
+        (camera.position, camera.rotation) = camera_path.sample(time);
+
+        animate(&mut scene.objects, &scene.animations, scene_time, time);
+        simulate(&mut scene.objects, &mut scene.bodies, time - scene_time);
+        scene_time = time;
+
+        if let Some(ascii) = &mut ascii {
+            renderer.cache.invalidate();
+            ascii.write_frame(time - start, renderer.render_frame(scene, &camera))?;
+        }
+
+        let pixels = render_image(scene, &camera, settings);
+
+        let path = format!(
+            "{}/frame_{frame:04}.{}",
+            settings.output,
+            image_extension(settings.image_format)
+        );
+        write_image(
+            &path,
+            settings.image_format,
+            settings.image_width,
+            settings.image_height,
+            &pixels,
+        )?;
+
+        println!("rendered {path} ({}/{})", frame + 1, settings.frames);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAMP: [char; 4] = [' ', '.', 'o', '#'];
+
+    #[test]
+    fn intensities_outside_the_range_clamp_to_the_ends() {
+        assert_eq!(intensity_to_char(-1.0, &RAMP, 1.0), ' ');
+        assert_eq!(intensity_to_char(0.0, &RAMP, 1.0), ' ');
+        assert_eq!(intensity_to_char(1.0, &RAMP, 1.0), '#');
+        assert_eq!(intensity_to_char(5.0, &RAMP, 1.0), '#');
+        assert_eq!(intensity_to_char(f32::INFINITY, &RAMP, 1.0), '#');
+    }
+
+    #[test]
+    fn intensities_pick_evenly_spaced_characters() {
+        assert_eq!(intensity_to_char(0.3, &RAMP, 1.0), '.');
+        assert_eq!(intensity_to_char(0.6, &RAMP, 1.0), 'o');
+        // Gamma above one brightens the midtones.
+        assert_eq!(intensity_to_char(0.3, &RAMP, 2.0), 'o');
+    }
+
+    #[test]
+    fn grey_radiance_keeps_its_intensity() {
+        for i in [0.0, 0.25, 0.5, 1.0] {
+            assert!((luminance(Vec3::splat(i)) - i).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn pixels_clamp_each_channel() {
+        assert_eq!(radiance_to_rgb(Vec3::new(-0.5, 0.5, 2.0)), [0, 128, 255]);
+        assert_eq!(radiance_to_rgb(Vec3::ONE), [255; 3]);
+    }
+
+    #[test]
+    fn braille_lights_dots_in_proportion() {
+        assert_eq!(encode_braille([Some(0.0); 8], 1.0), '\u{2800}');
+        assert_eq!(encode_braille([Some(1.0); 8], 1.0), '\u{28ff}');
+        assert_eq!(encode_braille([None; 8], 1.0), '\u{2800}');
+
+        let half = encode_braille([Some(0.5); 8], 1.0);
+        assert_eq!(braille_bits(half).unwrap().count_ones(), 4);
+    }
+
+    #[test]
+    fn split_views_cover_the_grid_once_and_insets_stay_inside() {
+        let (left, right) = layout_rects(ViewLayout::Split);
+        let right = right.unwrap();
+        for i in 0..COLS * ROWS {
+            assert!(left.contains(i) != right.contains(i));
+        }
+        assert_eq!(left.cells().count() + right.cells().count(), COLS * ROWS);
+
+        let (main, inset) = layout_rects(ViewLayout::Inset);
+        assert_eq!(main, Rect::FULL);
+        assert!(inset
+            .unwrap()
+            .cells()
+            .all(|i| main.contains(i) && i < COLS * ROWS));
+    }
+}
@@ -0,0 +1,83 @@
+use notan::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+
+// Everything update() reads from outside in one frame. Sessions are stored as
+// one frame per line of JSON, so a recording cut short is still readable up to
+// where it stopped.
+#[derive(Serialize, Deserialize)]
+pub struct InputFrame {
+    // Seconds since the recording started, at the end of this frame.
+    pub time: f32,
+    pub dt: f32,
+    pub down: Vec<KeyCode>,
+    pub pressed: Vec<KeyCode>,
+    pub click: bool,
+    pub axes: Option<[f32; 8]>,
+}
+
+impl InputFrame {
+    pub fn capture(app: &App, time: f32, dt: f32, axes: Option<[f32; 8]>) -> Self {
+        // Sorted so the same input always writes the same line.
+        let mut down: Vec<KeyCode> = app.keyboard.down.keys().copied().collect();
+        let mut pressed: Vec<KeyCode> = app.keyboard.pressed.iter().copied().collect();
+        down.sort();
+        pressed.sort();
+
+        InputFrame {
+            time,
+            dt,
+            down,
+            pressed,
+            click: app.mouse.left_was_pressed(),
+            axes,
+        }
+    }
+
+    // Swaps the live keyboard and mouse state for the recorded one.
+    pub fn apply(&self, app: &mut App) {
+        app.keyboard.down = self.down.iter().map(|key| (*key, 0.0)).collect();
+        app.keyboard.pressed = self.pressed.iter().copied().collect();
+        app.mouse.pressed.clear();
+        if self.click {
+            app.mouse.pressed.insert(MouseButton::Left);
+        }
+    }
+}
+
+pub struct Recorder {
+    writer: LineWriter<File>,
+    time: f32,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+        Ok(Recorder {
+            writer: LineWriter::new(file),
+            time: 0.0,
+        })
+    }
+
+    pub fn record(&mut self, app: &App, dt: f32, axes: Option<[f32; 8]>) -> Result<(), String> {
+        self.time += dt;
+        let frame = InputFrame::capture(app, self.time, dt, axes);
+
+        let line = serde_json::to_string(&frame).map_err(|e| e.to_string())?;
+        writeln!(self.writer, "{line}").map_err(|e| e.to_string())
+    }
+}
+
+pub fn load_session(path: &str) -> Result<Vec<InputFrame>, String> {
+    let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.map_err(|e| format!("{path}: {e}"))?;
+            serde_json::from_str(&line).map_err(|e| format!("{path}:{}: {e}", i + 1))
+        })
+        .collect()
+}
@@ -0,0 +1,1588 @@
+use crate::noise::fractal_noise;
+use notan::math::{EulerRot, Mat3, Quat, Vec3};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Arc;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Triangle {
+    pub vertex1: Vec3,
+    pub vertex2: Vec3,
+    pub vertex3: Vec3,
+    // Surface normals at each vertex, blended across the face so a mesh of
+    // flat triangles shades like the curved surface it stands in for. Without
+    // them the face is shaded flat.
+    #[serde(default)]
+    pub normals: Option<[Vec3; 3]>,
+    #[serde(default)]
+    pub texture: Option<Texture>,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+    #[serde(default)]
+    pub texture: Option<Texture>,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Cuboid {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+    #[serde(
+        default,
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    pub rotation: Mat3,
+    #[serde(default)]
+    pub texture: Option<Texture>,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+// Surface patterns looked up by the (u, v) coordinates of a hit, both in
+// [0, 1], to give the albedo there.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Texture {
+    // Alternating light and dark squares, `size` wide in uv space.
+    Checker {
+        size: f32,
+    },
+    // A PNG file, loaded when the scene is read. Only its brightness is used.
+    Image(
+        #[serde(
+            serialize_with = "serialize_image",
+            deserialize_with = "deserialize_image"
+        )]
+        TextureImage,
+    ),
+}
+
+// Images keep the path they were loaded from so that scenes can be saved
+// again.
+#[derive(Clone)]
+pub struct TextureImage {
+    pub path: String,
+    pub width: usize,
+    pub height: usize,
+    // Shared, since scenes are cloned for every frame traced in the
+    // background.
+    pub pixels: Arc<[f32]>,
+}
+
+pub fn deserialize_image<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<TextureImage, D::Error> {
+    let path = String::deserialize(deserializer)?;
+    load_texture_image(&path).map_err(serde::de::Error::custom)
+}
+
+pub fn serialize_image<S: Serializer>(
+    image: &TextureImage,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    image.path.serialize(serializer)
+}
+
+// Scenes and textures shipped with cast. Browsers have no filesystem to read
+// them from, so the WebAssembly build carries its own copies, found by the
+// same relative paths.
+#[cfg(target_arch = "wasm32")]
+const EMBEDDED_ASSETS: &[(&str, &[u8])] = &[
+    (
+        "scenes/animated.json",
+        include_bytes!("../scenes/animated.json"),
+    ),
+    ("scenes/csg.json", include_bytes!("../scenes/csg.json")),
+    (
+        "scenes/example.json",
+        include_bytes!("../scenes/example.json"),
+    ),
+    (
+        "scenes/flythrough.json",
+        include_bytes!("../scenes/flythrough.json"),
+    ),
+    (
+        "scenes/physics.json",
+        include_bytes!("../scenes/physics.json"),
+    ),
+    (
+        "scenes/instances.json",
+        include_bytes!("../scenes/instances.json"),
+    ),
+    (
+        "scenes/lights.json",
+        include_bytes!("../scenes/lights.json"),
+    ),
+    ("scenes/sdf.json", include_bytes!("../scenes/sdf.json")),
+    ("scenes/sky.json", include_bytes!("../scenes/sky.json")),
+    (
+        "scenes/terrain.json",
+        include_bytes!("../scenes/terrain.json"),
+    ),
+    (
+        "scenes/textured.json",
+        include_bytes!("../scenes/textured.json"),
+    ),
+    (
+        "assets/textures/bricks.png",
+        include_bytes!("../assets/textures/bricks.png"),
+    ),
+];
+
+#[cfg(not(target_arch = "wasm32"))]
+fn open_asset(path: &str) -> Result<impl Read, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    Ok(std::io::BufReader::new(file))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn open_asset(path: &str) -> Result<impl Read, String> {
+    EMBEDDED_ASSETS
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, bytes)| *bytes)
+        .ok_or(format!("{path}: not one of the files built in"))
+}
+
+pub fn load_texture_image(path: &str) -> Result<TextureImage, String> {
+    let mut decoder = png::Decoder::new(open_asset(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| format!("{path}: {e}"))?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|e| format!("{path}: {e}"))?;
+
+    let channels = info.color_type.samples();
+    let pixels = buffer[..info.buffer_size()]
+        .chunks(channels)
+        .map(|p| {
+            let luma = match p {
+                [r, g, b, ..] => 0.2126 * *r as f32 + 0.7152 * *g as f32 + 0.0722 * *b as f32,
+                [l, ..] => *l as f32,
+                [] => 0.0,
+            };
+            luma / 255.0
+        })
+        .collect();
+
+    Ok(TextureImage {
+        path: path.to_string(),
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+impl Texture {
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        match self {
+            Texture::Checker { size } => {
+                let parity = (u / size).floor() + (v / size).floor();
+                if parity.rem_euclid(2.0) < 1.0 {
+                    1.0
+                } else {
+                    0.5
+                }
+            }
+            Texture::Image(image) => image.sample(u, v),
+        }
+    }
+}
+
+impl TextureImage {
+    // Bilinear filtering with wrapping, with v = 0 at the bottom of the image.
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.rem_euclid(1.0) * self.width as f32 - 0.5;
+        let y = (1.0 - v.rem_euclid(1.0)) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |x: f32, y: f32| {
+            let x = (x as i64).rem_euclid(self.width as i64) as usize;
+            let y = (y as i64).rem_euclid(self.height as i64) as usize;
+            self.pixels[y * self.width + x]
+        };
+
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+// Untextured surfaces are plain white. Textures are greyscale, so they
+// scale all three channels alike.
+pub fn texture_albedo(texture: &Option<Texture>, uv: impl FnOnce() -> (f32, f32)) -> Vec3 {
+    texture.as_ref().map_or(Vec3::ONE, |texture| {
+        let (u, v) = uv();
+        Vec3::splat(texture.sample(u, v))
+    })
+}
+
+// Rotations are written in scene files as [x, y, z] Euler angles in degrees,
+// applied in yaw, pitch, roll order.
+pub fn deserialize_rotation<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Mat3, D::Error> {
+    let [x, y, z] = <[f32; 3]>::deserialize(deserializer)?;
+
+    Ok(Mat3::from_quat(Quat::from_euler(
+        EulerRot::YXZ,
+        y.to_radians(),
+        x.to_radians(),
+        z.to_radians(),
+    )))
+}
+
+pub fn serialize_rotation<S: Serializer>(
+    rotation: &Mat3,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let (y, x, z) = Quat::from_mat3(rotation).to_euler(EulerRot::YXZ);
+    [x.to_degrees(), y.to_degrees(), z.to_degrees()].serialize(serializer)
+}
+
+// An infinite plane through `point`. When `checker` is set the surface is
+// shaded as a checkerboard with squares of that size.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    #[serde(default)]
+    pub checker: Option<f32>,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+// A capped cylinder whose axis runs from `start` to `end`.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Cylinder {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub radius: f32,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+// A cylinder with hemispherical ends centred on `start` and `end`.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Capsule {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub radius: f32,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+// Unrotated, the torus lies flat in the xz plane around its center.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Torus {
+    pub center: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    #[serde(
+        default,
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    pub rotation: Mat3,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+// Distance functions composed in a scene file. Mandelbulb is a fractal with no
+// analytic intersection, which is where sphere tracing earns its keep.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sdf {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Box {
+        center: Vec3,
+        half_extents: Vec3,
+    },
+    Torus {
+        center: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Mandelbulb {
+        power: f32,
+        iterations: usize,
+    },
+    Union(Vec<Sdf>),
+    Intersection(Vec<Sdf>),
+    Difference(Box<Sdf>, Box<Sdf>),
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f32),
+}
+
+impl Sdf {
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            Sdf::Sphere { center, radius } => (p - *center).length() - radius,
+            Sdf::Box {
+                center,
+                half_extents,
+            } => {
+                let q = (p - *center).abs() - *half_extents;
+                q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+            }
+            Sdf::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let q = p - *center;
+                let ring = Vec3::new(q.x, 0.0, q.z).length() - major_radius;
+                (ring * ring + q.y * q.y).sqrt() - minor_radius
+            }
+            Sdf::Mandelbulb { power, iterations } => mandelbulb_distance(p, *power, *iterations),
+            Sdf::Union(sdfs) => sdfs
+                .iter()
+                .map(|s| s.distance(p))
+                .fold(f32::INFINITY, f32::min),
+            Sdf::Intersection(sdfs) => sdfs
+                .iter()
+                .map(|s| s.distance(p))
+                .fold(f32::NEG_INFINITY, f32::max),
+            Sdf::Difference(a, b) => a.distance(p).max(-b.distance(p)),
+            // Without any blending radius it's just the union, which the
+            // formula below would divide by zero to get.
+            Sdf::SmoothUnion(a, b, k) if *k <= 0.0 => a.distance(p).min(b.distance(p)),
+            Sdf::SmoothUnion(a, b, k) => {
+                let (a, b) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+                b + (a - b) * h - k * h * (1.0 - h)
+            }
+        }
+    }
+}
+
+pub fn mandelbulb_distance(p: Vec3, power: f32, iterations: usize) -> f32 {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = 0.0;
+
+    for _ in 0..iterations {
+        r = z.length();
+        if r > 2.0 {
+            break;
+        }
+
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        z = r.powf(power)
+            * Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            )
+            + p;
+    }
+
+    0.5 * r.ln() * r / dr
+}
+
+pub fn default_sdf_scale() -> f32 {
+    1.0
+}
+
+// A height field over a square `size` on a side, centred on `center` with
+// its lowest points there and its highest `height` above. The heightmap's
+// first row lies along the far (+z) edge, as if it were a map looking down
+// with +x to the right.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Terrain {
+    pub center: Vec3,
+    pub size: f32,
+    pub height: f32,
+    pub heightmap: Heightmap,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Heightmap {
+    // A PNG's brightness, one grid point per pixel.
+    Image(
+        #[serde(
+            serialize_with = "serialize_image",
+            deserialize_with = "deserialize_image"
+        )]
+        TextureImage,
+    ),
+    Noise(NoiseMap),
+}
+
+// Fractal Perlin noise, as written in a scene file.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct Noise {
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default = "default_noise_resolution")]
+    pub resolution: usize,
+    #[serde(default = "default_noise_frequency")]
+    pub frequency: f32,
+    #[serde(default = "default_noise_octaves")]
+    pub octaves: usize,
+}
+
+pub fn default_noise_resolution() -> usize {
+    64
+}
+
+pub fn default_noise_frequency() -> f32 {
+    4.0
+}
+
+pub fn default_noise_octaves() -> usize {
+    4
+}
+
+// Past these the heights take too long to work out, or the finest octave
+// is too faint to see.
+const MAX_NOISE_RESOLUTION: usize = 2048;
+const MAX_NOISE_OCTAVES: usize = 16;
+
+// Noise heights are worked out once when the scene is read, and the
+// parameters kept so that it can be written out again.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(from = "Noise", into = "Noise")]
+pub struct NoiseMap {
+    pub noise: Noise,
+    pub heights: Arc<[f32]>,
+}
+
+impl From<Noise> for NoiseMap {
+    fn from(noise: Noise) -> Self {
+        let noise = Noise {
+            resolution: noise.resolution.clamp(2, MAX_NOISE_RESOLUTION),
+            octaves: noise.octaves.min(MAX_NOISE_OCTAVES),
+            ..noise
+        };
+        NoiseMap {
+            noise,
+            heights: fractal_noise(noise.seed, noise.resolution, noise.frequency, noise.octaves)
+                .into(),
+        }
+    }
+}
+
+impl From<NoiseMap> for Noise {
+    fn from(map: NoiseMap) -> Self {
+        map.noise
+    }
+}
+
+impl Heightmap {
+    // The number of columns and rows of grid points.
+    pub fn size(&self) -> (usize, usize) {
+        match self {
+            Heightmap::Image(image) => (image.width, image.height),
+            Heightmap::Noise(map) => (map.noise.resolution, map.noise.resolution),
+        }
+    }
+
+    // From 0 at the lowest to 1 at the highest, with row 0 along the near
+    // (-z) edge.
+    pub fn height(&self, column: usize, row: usize) -> f32 {
+        let (columns, rows) = self.size();
+        let row = rows - 1 - row;
+        match self {
+            Heightmap::Image(image) => image.pixels[row * columns + column],
+            Heightmap::Noise(map) => map.heights[row * columns + column],
+        }
+    }
+}
+
+// A distance function placed in the world, found by sphere tracing rather than
+// an analytic intersection.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SdfObject {
+    pub sdf: Sdf,
+    #[serde(default)]
+    pub position: Vec3,
+    #[serde(default = "default_sdf_scale")]
+    pub scale: f32,
+    #[serde(
+        default,
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    pub rotation: Mat3,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+impl SdfObject {
+    pub fn distance(&self, p: Vec3) -> f32 {
+        let local = self.rotation.transpose() * (p - self.position) / self.scale;
+        self.sdf.distance(local) * self.scale
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Object {
+    Sphere(Sphere),
+    Cuboid(Cuboid),
+    Triangle(Triangle),
+    Plane(Plane),
+    Cylinder(Cylinder),
+    Capsule(Capsule),
+    Torus(Torus),
+    Terrain(Terrain),
+    Sdf(SdfObject),
+    Csg(Csg),
+    Instance(Instance),
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+// Boolean combination of two solids. Like SDF objects the operands are written
+// in the node's local frame, so the whole shape can be moved as one.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub a: Box<Object>,
+    pub b: Box<Object>,
+    #[serde(default)]
+    pub position: Vec3,
+    #[serde(default = "default_sdf_scale")]
+    pub scale: f32,
+    #[serde(
+        default,
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    pub rotation: Mat3,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+// Objects written once in a scene and placed any number of times by
+// instances, which all share the one copy. The mesh's triangles are given
+// vertex normals by load_scene and shade smoothly, unless it's marked flat.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Mesh {
+    pub name: String,
+    pub objects: Arc<[Object]>,
+    #[serde(default)]
+    pub flat: bool,
+}
+
+// Faces meeting at a sharper angle than this keep a hard edge between them
+// when vertex normals are worked out, so the corners of boxes stay square.
+const CREASE_ANGLE: f32 = 60.0;
+
+// Gives every triangle among `objects` that has no normals of its own the
+// average of the face normals around each of its vertices, counting only the
+// faces within CREASE_ANGLE of its own. Vertices are shared by position, and
+// larger faces count for more.
+pub fn smooth_normals(objects: &mut [Object]) {
+    let key = |v: Vec3| v.to_array().map(f32::to_bits);
+    let faces: Vec<(usize, [Vec3; 3], Vec3)> = objects
+        .iter()
+        .enumerate()
+        .filter_map(|(i, object)| match object {
+            Object::Triangle(triangle) if triangle.normals.is_none() => {
+                let vertices = [triangle.vertex1, triangle.vertex2, triangle.vertex3];
+                let area = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]);
+                Some((i, vertices, area))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut around: HashMap<[u32; 3], Vec<Vec3>> = HashMap::new();
+    for (_, vertices, area) in &faces {
+        for &vertex in vertices {
+            around.entry(key(vertex)).or_default().push(*area);
+        }
+    }
+
+    let crease = CREASE_ANGLE.to_radians().cos();
+    for (i, vertices, area) in faces {
+        let normal = area.normalize_or_zero();
+        let normals = vertices.map(|vertex| {
+            around[&key(vertex)]
+                .iter()
+                .filter(|other| other.normalize_or_zero().dot(normal) >= crease)
+                .sum::<Vec3>()
+                .try_normalize()
+                .unwrap_or(normal)
+        });
+        if let Object::Triangle(triangle) = &mut objects[i] {
+            triangle.normals = Some(normals);
+        }
+    }
+}
+
+// A copy of the mesh called `mesh`, moved, rotated and uniformly scaled as a
+// whole. The mesh's objects are linked in by load_scene.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Instance {
+    pub mesh: String,
+    #[serde(skip)]
+    pub objects: Arc<[Object]>,
+    #[serde(default)]
+    pub position: Vec3,
+    #[serde(default = "default_sdf_scale")]
+    pub scale: f32,
+    #[serde(
+        default,
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    pub rotation: Mat3,
+    #[serde(default)]
+    pub emission: f32,
+}
+
+// Points every instance among `objects`, including those inside CSG nodes,
+// at the mesh it names.
+pub fn link_instances(objects: &mut [Object], meshes: &[Mesh]) -> Result<(), String> {
+    for object in objects {
+        match object {
+            Object::Instance(instance) => {
+                let mesh = meshes
+                    .iter()
+                    .find(|m| m.name == instance.mesh)
+                    .ok_or(format!("instance of missing mesh {}", instance.mesh))?;
+                instance.objects = mesh.objects.clone();
+            }
+            Object::Csg(csg) => {
+                link_instances(std::slice::from_mut(&mut *csg.a), meshes)?;
+                link_instances(std::slice::from_mut(&mut *csg.b), meshes)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Scene {
+    pub objects: Vec<Object>,
+    #[serde(default)]
+    pub meshes: Vec<Mesh>,
+    #[serde(default)]
+    pub animations: Vec<Animation>,
+    #[serde(default)]
+    pub bodies: Vec<Body>,
+    #[serde(default)]
+    pub background: Option<Background>,
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+    #[serde(default)]
+    pub cameras: Vec<SceneCamera>,
+}
+
+// What rays that miss every object see. Without one they stay blank.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Background {
+    #[serde(flatten)]
+    pub sky: Sky,
+    #[serde(default)]
+    pub sun: Option<Sun>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sky {
+    // Blends from `horizon` up to `zenith` above and down to `ground` below.
+    Gradient {
+        horizon: f32,
+        zenith: f32,
+        #[serde(default)]
+        ground: f32,
+    },
+    // An equirectangular PNG wrapped around the scene, with its middle row
+    // on the horizon and its center straight ahead along +z.
+    Image(
+        #[serde(
+            serialize_with = "serialize_image",
+            deserialize_with = "deserialize_image"
+        )]
+        TextureImage,
+    ),
+}
+
+// A bright disc `size` degrees across in the direction of the sun.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Sun {
+    pub direction: Vec3,
+    pub size: f32,
+    #[serde(default = "default_sun_intensity")]
+    pub intensity: f32,
+}
+
+pub fn default_sun_intensity() -> f32 {
+    1.0
+}
+
+impl Background {
+    // The radiance seen looking in `direction`, grey like the textures.
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        let d = direction.normalize();
+
+        let sky = match &self.sky {
+            Sky::Gradient {
+                horizon,
+                zenith,
+                ground,
+            } => {
+                if d.y >= 0.0 {
+                    horizon + (zenith - horizon) * d.y
+                } else {
+                    horizon + (ground - horizon) * -d.y
+                }
+            }
+            Sky::Image(image) => image.sample(
+                0.5 + d.x.atan2(d.z) / std::f32::consts::TAU,
+                0.5 + d.y.clamp(-1.0, 1.0).asin() / std::f32::consts::PI,
+            ),
+        };
+
+        let Some(sun) = &self.sun else {
+            return Vec3::splat(sky);
+        };
+
+        // Soften the edge of the disc over its outer fifth.
+        let angle = d.dot(sun.direction.normalize()).clamp(-1.0, 1.0).acos();
+        let radius = (sun.size / 2.0).to_radians();
+        let disc = ((radius - angle) / (radius * 0.2)).clamp(0.0, 1.0);
+        Vec3::splat(sky + (sun.intensity - sky).max(0.0) * disc)
+    }
+}
+
+// Moves the object at index `object` in the scene over time.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Animation {
+    pub object: usize,
+    #[serde(flatten)]
+    pub motion: Motion,
+}
+
+pub fn default_axis() -> Vec3 {
+    Vec3::Y
+}
+
+// Motions are given in seconds. Periods are the time for one full cycle.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Motion {
+    // Circles `center` about `axis`, keeping the object's own orientation.
+    Orbit {
+        center: Vec3,
+        #[serde(default = "default_axis")]
+        axis: Vec3,
+        period: f32,
+    },
+    // Turns the object in place about `axis`.
+    Spin {
+        #[serde(default = "default_axis")]
+        axis: Vec3,
+        period: f32,
+    },
+    // Swings back and forth by up to `amplitude` either side of the start.
+    Oscillate {
+        amplitude: Vec3,
+        period: f32,
+    },
+    // Offsets and rotations relative to the object's starting pose,
+    // interpolated between keyframes and optionally repeated.
+    Keyframes {
+        keyframes: Vec<ObjectKeyframe>,
+        #[serde(default, rename = "loop")]
+        repeat: bool,
+    },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ObjectKeyframe {
+    pub time: f32,
+    #[serde(default)]
+    pub offset: Vec3,
+    #[serde(
+        default,
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    pub rotation: Mat3,
+}
+
+impl Motion {
+    // The offset and rotation of the object at `time`, relative to where it
+    // started.
+    pub fn pose(&self, time: f32) -> (Vec3, Mat3) {
+        let angle = |period: f32| std::f32::consts::TAU * time / period;
+
+        match self {
+            Motion::Orbit { .. } => (Vec3::default(), Mat3::IDENTITY),
+            Motion::Spin { axis, period } => (
+                Vec3::default(),
+                Mat3::from_axis_angle(axis.normalize(), angle(*period)),
+            ),
+            Motion::Oscillate { amplitude, period } => {
+                (*amplitude * angle(*period).sin(), Mat3::IDENTITY)
+            }
+            Motion::Keyframes { keyframes, repeat } => {
+                let (Some(first), Some(last)) = (keyframes.first(), keyframes.last()) else {
+                    return (Vec3::default(), Mat3::IDENTITY);
+                };
+
+                let span = last.time - first.time;
+                let time = if *repeat && span > 0.0 {
+                    first.time + (time - first.time).rem_euclid(span)
+                } else {
+                    time
+                };
+
+                let next = keyframes
+                    .iter()
+                    .position(|k| k.time > time)
+                    .unwrap_or(keyframes.len() - 1);
+                let prev = next.saturating_sub(1);
+
+                let (a, b) = (&keyframes[prev], &keyframes[next]);
+                let f = if b.time > a.time {
+                    ((time - a.time) / (b.time - a.time)).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+
+                let rotation = Quat::from_mat3(&a.rotation).slerp(Quat::from_mat3(&b.rotation), f);
+                (a.offset.lerp(b.offset, f), Mat3::from_quat(rotation))
+            }
+        }
+    }
+
+    // Moves `object` from its pose at `from` to its pose at `to`. Objects are
+    // moved incrementally so that edits made in between are kept.
+    pub fn advance(&self, object: &mut Object, from: f32, to: f32) {
+        if let Motion::Orbit {
+            center,
+            axis,
+            period,
+        } = self
+        {
+            let angle = std::f32::consts::TAU * (to - from) / period;
+            let arm = object.origin() - *center;
+            object.translate(Mat3::from_axis_angle(axis.normalize(), angle) * arm - arm);
+            return;
+        }
+
+        let (from_offset, from_rotation) = self.pose(from);
+        let (to_offset, to_rotation) = self.pose(to);
+
+        object.translate(to_offset - from_offset);
+        object.rotate(to_rotation * from_rotation.transpose());
+    }
+}
+
+// A sphere in the scene that falls under gravity and bounces off planes and
+// other spheres.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Body {
+    pub object: usize,
+    #[serde(default)]
+    pub velocity: Vec3,
+}
+
+pub const GRAVITY: Vec3 = Vec3::new(0.0, -9.81, 0.0);
+// Fraction of the closing speed kept after a bounce.
+pub const RESTITUTION: f32 = 0.7;
+// Bounces slower than this come to rest instead, so settled balls stop
+// jittering on the floor.
+pub const REST_SPEED: f32 = 0.2;
+// Per-second damping of sliding while touching a plane.
+pub const FRICTION: f32 = 0.8;
+// Simulation step, and the most steps taken per frame so a long frame
+// doesn't stall the next one.
+pub const PHYSICS_STEP: f32 = 1.0 / 120.0;
+pub const MAX_PHYSICS_STEPS: usize = 8;
+
+pub fn simulate(objects: &mut [Object], bodies: &mut [Body], dt: f32) {
+    let steps = ((dt / PHYSICS_STEP).ceil() as usize).clamp(1, MAX_PHYSICS_STEPS);
+    let dt = dt / steps as f32;
+
+    for _ in 0..steps {
+        for body in bodies.iter_mut() {
+            body.velocity += GRAVITY * dt;
+            if let Some(Object::Sphere(sphere)) = objects.get_mut(body.object) {
+                sphere.center += body.velocity * dt;
+            }
+        }
+
+        collide_bodies(objects, bodies, dt);
+    }
+}
+
+pub fn collide_bodies(objects: &mut [Object], bodies: &mut [Body], dt: f32) {
+    for a in 0..bodies.len() {
+        let Some(Object::Sphere(sphere)) = objects.get(bodies[a].object) else {
+            continue;
+        };
+        let (mut center, radius) = (sphere.center, sphere.radius);
+        let mut velocity = bodies[a].velocity;
+
+        for (i, object) in objects.iter().enumerate() {
+            match object {
+                Object::Plane(plane) => {
+                    let normal = plane.normal.normalize();
+                    let depth = radius - (center - plane.point).dot(normal);
+                    if depth <= 0.0 {
+                        continue;
+                    }
+
+                    center += normal * depth;
+                    let closing = velocity.dot(normal);
+                    if closing < 0.0 {
+                        let bounce = if -closing < REST_SPEED {
+                            0.0
+                        } else {
+                            RESTITUTION
+                        };
+                        velocity -= (1.0 + bounce) * closing * normal;
+
+                        let sliding = velocity - velocity.dot(normal) * normal;
+                        velocity -= sliding * (FRICTION * dt).min(1.0);
+                    }
+                }
+                // Spheres that aren't bodies stay put, as if infinitely heavy.
+                Object::Sphere(other)
+                    if i != bodies[a].object && !bodies.iter().any(|b| b.object == i) =>
+                {
+                    let offset = center - other.center;
+                    let depth = radius + other.radius - offset.length();
+                    if depth <= 0.0 {
+                        continue;
+                    }
+
+                    let normal = offset.normalize_or_zero();
+                    center += normal * depth;
+                    let closing = velocity.dot(normal);
+                    if closing < 0.0 {
+                        velocity -= (1.0 + RESTITUTION) * closing * normal;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(Object::Sphere(sphere)) = objects.get_mut(bodies[a].object) {
+            sphere.center = center;
+        }
+        bodies[a].velocity = velocity;
+
+        // Pairs of bodies push each other apart in proportion to their mass.
+        for b in a + 1..bodies.len() {
+            let (Some(Object::Sphere(first)), Some(Object::Sphere(second))) =
+                (objects.get(bodies[a].object), objects.get(bodies[b].object))
+            else {
+                continue;
+            };
+
+            let offset = second.center - first.center;
+            let depth = first.radius + second.radius - offset.length();
+            if depth <= 0.0 {
+                continue;
+            }
+
+            let normal = offset.normalize_or_zero();
+            let (mass_a, mass_b) = (first.radius.powi(3), second.radius.powi(3));
+            let share_a = mass_b / (mass_a + mass_b);
+            let share_b = mass_a / (mass_a + mass_b);
+
+            let closing = (bodies[b].velocity - bodies[a].velocity).dot(normal);
+            if closing < 0.0 {
+                let impulse = (1.0 + RESTITUTION) * closing;
+                bodies[a].velocity += impulse * share_a * normal;
+                bodies[b].velocity -= impulse * share_b * normal;
+            }
+
+            if let Some(Object::Sphere(first)) = objects.get_mut(bodies[a].object) {
+                first.center -= normal * depth * share_a;
+            }
+            if let Some(Object::Sphere(second)) = objects.get_mut(bodies[b].object) {
+                second.center += normal * depth * share_b;
+            }
+        }
+    }
+}
+
+pub fn animate(objects: &mut [Object], animations: &[Animation], from: f32, to: f32) {
+    for animation in animations {
+        if let Some(object) = objects.get_mut(animation.object) {
+            animation.motion.advance(object, from, to);
+        }
+    }
+}
+
+// Animates the scene from `from` to `to` and simulates its bodies over the
+// time between. `moved` is given each object that moves both before and
+// after, so whatever showed it can be redrawn. Bodies at rest aren't given.
+pub fn step_scene(scene: &mut Scene, from: f32, to: f32, mut moved: impl FnMut(&Object)) {
+    for animation in &scene.animations {
+        let Some(object) = scene.objects.get_mut(animation.object) else {
+            continue;
+        };
+
+        moved(object);
+        animation.motion.advance(object, from, to);
+        moved(object);
+    }
+
+    let before: Vec<Object> = scene
+        .bodies
+        .iter()
+        .map(|b| scene.objects[b.object].clone())
+        .collect();
+
+    simulate(&mut scene.objects, &mut scene.bodies, to - from);
+
+    for (body, before) in scene.bodies.iter().zip(before) {
+        let object = &scene.objects[body.object];
+        if object.origin().distance(before.origin()) > 1e-5 {
+            moved(&before);
+            moved(object);
+        }
+    }
+}
+
+// Takes an object out of the scene, along with its animations and body, and
+// keeps the rest pointing at the same objects once they shift down to fill
+// the gap.
+pub fn remove_object(scene: &mut Scene, index: usize) {
+    scene.objects.remove(index);
+
+    scene.animations.retain(|a| a.object != index);
+    for animation in &mut scene.animations {
+        if animation.object > index {
+            animation.object -= 1;
+        }
+    }
+    scene.bodies.retain(|b| b.object != index);
+    for body in &mut scene.bodies {
+        if body.object > index {
+            body.object -= 1;
+        }
+    }
+}
+
+// A camera pose saved to one of the slots 1 to 9 with Ctrl and the number,
+// and recalled with Shift and the number.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Bookmark {
+    pub slot: usize,
+    pub position: Vec3,
+    #[serde(
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    pub rotation: Mat3,
+}
+
+// A viewpoint set up in the scene file, which can be switched to with C or
+// shown beside the active camera. Without a field of view it keeps the one
+// from the command line.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SceneCamera {
+    pub name: String,
+    pub position: Vec3,
+    #[serde(
+        default = "default_camera_rotation",
+        serialize_with = "serialize_rotation",
+        deserialize_with = "deserialize_rotation"
+    )]
+    pub rotation: Mat3,
+    #[serde(default)]
+    pub fov: Option<f32>,
+}
+
+pub fn default_camera_rotation() -> Mat3 {
+    Mat3::IDENTITY
+}
+
+impl Object {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Object::Sphere(_) => "sphere",
+            Object::Cuboid(_) => "cuboid",
+            Object::Triangle(_) => "triangle",
+            Object::Plane(_) => "plane",
+            Object::Cylinder(_) => "cylinder",
+            Object::Capsule(_) => "capsule",
+            Object::Torus(_) => "torus",
+            Object::Terrain(_) => "terrain",
+            Object::Sdf(_) => "sdf",
+            Object::Csg(_) => "csg",
+            Object::Instance(_) => "instance",
+        }
+    }
+
+    // Any object can be a light: its surface gives off this much on top of
+    // what it reflects. Only the outside of a CSG node counts, not its
+    // operands.
+    pub fn emission(&self) -> f32 {
+        match self {
+            Object::Sphere(sphere) => sphere.emission,
+            Object::Cuboid(cuboid) => cuboid.emission,
+            Object::Triangle(triangle) => triangle.emission,
+            Object::Plane(plane) => plane.emission,
+            Object::Cylinder(cylinder) => cylinder.emission,
+            Object::Capsule(capsule) => capsule.emission,
+            Object::Torus(torus) => torus.emission,
+            Object::Terrain(terrain) => terrain.emission,
+            Object::Sdf(sdf) => sdf.emission,
+            Object::Csg(csg) => csg.emission,
+            Object::Instance(instance) => instance.emission,
+        }
+    }
+
+    // A sphere enclosing the object, or None for objects that are unbounded
+    // or too costly to bound tightly.
+    pub fn bounds(&self) -> Option<(Vec3, f32)> {
+        match self {
+            Object::Sphere(sphere) => Some((sphere.center, sphere.radius)),
+            Object::Cuboid(cuboid) => Some((cuboid.center, cuboid.half_extents.length())),
+            Object::Triangle(triangle) => {
+                let centroid = (triangle.vertex1 + triangle.vertex2 + triangle.vertex3) / 3.0;
+                let radius = [triangle.vertex1, triangle.vertex2, triangle.vertex3]
+                    .iter()
+                    .map(|v| v.distance(centroid))
+                    .fold(0.0, f32::max);
+                Some((centroid, radius))
+            }
+            Object::Cylinder(Cylinder {
+                start, end, radius, ..
+            })
+            | Object::Capsule(Capsule {
+                start, end, radius, ..
+            }) => Some(((*start + *end) / 2.0, start.distance(*end) / 2.0 + radius)),
+            Object::Torus(torus) => Some((torus.center, torus.major_radius + torus.minor_radius)),
+            Object::Terrain(terrain) => {
+                let half = Vec3::new(terrain.size, terrain.height, terrain.size) / 2.0;
+                Some((terrain.center + Vec3::Y * half.y, half.length()))
+            }
+            // Around the instance's origin, taking in every object of the mesh.
+            Object::Instance(instance) => {
+                let radius = instance
+                    .objects
+                    .iter()
+                    .try_fold(0.0, |radius: f32, object| {
+                        let (center, r) = object.bounds()?;
+                        Some(radius.max(center.length() + r))
+                    })?;
+                Some((instance.position, radius * instance.scale))
+            }
+            Object::Plane(_) | Object::Sdf(_) | Object::Csg(_) => None,
+        }
+    }
+
+    // The point the object is positioned by.
+    pub fn origin(&self) -> Vec3 {
+        match self {
+            Object::Sphere(sphere) => sphere.center,
+            Object::Cuboid(cuboid) => cuboid.center,
+            Object::Triangle(triangle) => {
+                (triangle.vertex1 + triangle.vertex2 + triangle.vertex3) / 3.0
+            }
+            Object::Plane(plane) => plane.point,
+            Object::Cylinder(Cylinder { start, end, .. })
+            | Object::Capsule(Capsule { start, end, .. }) => (*start + *end) / 2.0,
+            Object::Torus(torus) => torus.center,
+            Object::Terrain(terrain) => terrain.center,
+            Object::Sdf(sdf) => sdf.position,
+            Object::Csg(csg) => csg.position,
+            Object::Instance(instance) => instance.position,
+        }
+    }
+
+    pub fn translate(&mut self, offset: Vec3) {
+        match self {
+            Object::Sphere(sphere) => sphere.center += offset,
+            Object::Cuboid(cuboid) => cuboid.center += offset,
+            Object::Triangle(triangle) => {
+                triangle.vertex1 += offset;
+                triangle.vertex2 += offset;
+                triangle.vertex3 += offset;
+            }
+            Object::Plane(plane) => plane.point += offset,
+            Object::Cylinder(Cylinder { start, end, .. })
+            | Object::Capsule(Capsule { start, end, .. }) => {
+                *start += offset;
+                *end += offset;
+            }
+            Object::Torus(torus) => torus.center += offset,
+            Object::Terrain(terrain) => terrain.center += offset,
+            Object::Sdf(sdf) => sdf.position += offset,
+            Object::Csg(csg) => csg.position += offset,
+            Object::Instance(instance) => instance.position += offset,
+        }
+    }
+
+    pub fn scale(&mut self, factor: f32) {
+        match self {
+            Object::Sphere(sphere) => sphere.radius *= factor,
+            Object::Cuboid(cuboid) => cuboid.half_extents *= factor,
+            Object::Triangle(triangle) => {
+                let centroid = (triangle.vertex1 + triangle.vertex2 + triangle.vertex3) / 3.0;
+                triangle.vertex1 = centroid + (triangle.vertex1 - centroid) * factor;
+                triangle.vertex2 = centroid + (triangle.vertex2 - centroid) * factor;
+                triangle.vertex3 = centroid + (triangle.vertex3 - centroid) * factor;
+            }
+            Object::Plane(plane) => {
+                if let Some(size) = &mut plane.checker {
+                    *size *= factor;
+                }
+            }
+            Object::Cylinder(Cylinder {
+                start, end, radius, ..
+            })
+            | Object::Capsule(Capsule {
+                start, end, radius, ..
+            }) => {
+                let middle = (*start + *end) / 2.0;
+                *start = middle + (*start - middle) * factor;
+                *end = middle + (*end - middle) * factor;
+                *radius *= factor;
+            }
+            Object::Torus(torus) => {
+                torus.major_radius *= factor;
+                torus.minor_radius *= factor;
+            }
+            Object::Terrain(terrain) => {
+                terrain.size *= factor;
+                terrain.height *= factor;
+            }
+            Object::Sdf(sdf) => sdf.scale *= factor,
+            Object::Csg(csg) => csg.scale *= factor,
+            Object::Instance(instance) => instance.scale *= factor,
+        }
+    }
+
+    pub fn rotate(&mut self, rotation: Mat3) {
+        match self {
+            // Spheres look the same either way, and terrain always lies level.
+            Object::Sphere(_) | Object::Terrain(_) => {}
+            Object::Cuboid(cuboid) => cuboid.rotation = rotation * cuboid.rotation,
+            Object::Triangle(triangle) => {
+                let centroid = (triangle.vertex1 + triangle.vertex2 + triangle.vertex3) / 3.0;
+                triangle.vertex1 = centroid + rotation * (triangle.vertex1 - centroid);
+                triangle.vertex2 = centroid + rotation * (triangle.vertex2 - centroid);
+                triangle.vertex3 = centroid + rotation * (triangle.vertex3 - centroid);
+                if let Some(normals) = &mut triangle.normals {
+                    *normals = normals.map(|normal| rotation * normal);
+                }
+            }
+            Object::Plane(plane) => plane.normal = rotation * plane.normal,
+            Object::Cylinder(Cylinder { start, end, .. })
+            | Object::Capsule(Capsule { start, end, .. }) => {
+                let middle = (*start + *end) / 2.0;
+                *start = middle + rotation * (*start - middle);
+                *end = middle + rotation * (*end - middle);
+            }
+            Object::Torus(torus) => torus.rotation = rotation * torus.rotation,
+            Object::Sdf(sdf) => sdf.rotation = rotation * sdf.rotation,
+            Object::Csg(csg) => csg.rotation = rotation * csg.rotation,
+            Object::Instance(instance) => instance.rotation = rotation * instance.rotation,
+        }
+    }
+}
+
+pub fn default_scene() -> Vec<Object> {
+    vec![
+        Object::Sphere(Sphere {
+            center: Vec3 {
+                x: 0.0,
+                y: -1.0,
+                z: 3.0,
+            },
+            radius: 1.0,
+            texture: None,
+            emission: 0.0,
+        }),
+        Object::Sphere(Sphere {
+            center: Vec3 {
+                x: 2.0,
+                y: 0.0,
+                z: 4.0,
+            },
+            radius: 1.0,
+            texture: None,
+            emission: 0.0,
+        }),
+        Object::Sphere(Sphere {
+            center: Vec3 {
+                x: -2.0,
+                y: 0.0,
+                z: 4.0,
+            },
+            radius: 1.0,
+            texture: None,
+            emission: 0.0,
+        }),
+        Object::Plane(Plane {
+            point: Vec3::new(0.0, -1.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            checker: Some(1.0),
+            emission: 0.0,
+        }),
+        Object::Triangle(Triangle {
+            vertex1: Vec3::new(0.0, -1.0, 1.0),
+            vertex2: Vec3::new(3.0, -1.0, -1.0),
+            vertex3: Vec3::new(1.0, 2.0, 1.0),
+            normals: None,
+            texture: None,
+            emission: 0.0,
+        }),
+        Object::Cuboid(Cuboid {
+            center: Vec3::new(-0.5, 0.5, 3.5),
+            half_extents: Vec3::new(0.5, 0.5, 0.5),
+            rotation: Mat3::default(),
+            texture: None,
+            emission: 0.0,
+        }),
+    ]
+}
+
+pub fn load_scene(path: &str) -> Result<Scene, String> {
+    let mut scene: Scene =
+        serde_json::from_reader(open_asset(path)?).map_err(|e| format!("{path}: {e}"))?;
+
+    // Meshes can place instances of the meshes listed before them.
+    for i in 0..scene.meshes.len() {
+        let (earlier, rest) = scene.meshes.split_at_mut(i);
+        let mesh = &mut rest[0];
+        let objects = Arc::get_mut(&mut mesh.objects).expect("meshes are unshared until linked");
+        if mesh.flat {
+            for object in objects.iter_mut() {
+                if let Object::Triangle(triangle) = object {
+                    triangle.normals = None;
+                }
+            }
+        } else {
+            smooth_normals(objects);
+        }
+        link_instances(objects, earlier).map_err(|e| format!("{path}: mesh {}: {e}", mesh.name))?;
+    }
+    link_instances(&mut scene.objects, &scene.meshes).map_err(|e| format!("{path}: {e}"))?;
+
+    if let Some(animation) = scene
+        .animations
+        .iter()
+        .find(|a| a.object >= scene.objects.len())
+    {
+        return Err(format!(
+            "{path}: animation refers to missing object {}",
+            animation.object
+        ));
+    }
+
+    if let Some(body) = scene
+        .bodies
+        .iter()
+        .find(|b| !matches!(scene.objects.get(b.object), Some(Object::Sphere(_))))
+    {
+        return Err(format!("{path}: body {} is not a sphere", body.object));
+    }
+
+    Ok(scene)
+}
+
+// Writes the scene as it is `time` seconds in, in the format load_scene
+// reads. Animated objects are rewound to where they started so their motions
+// replay the same way when it is loaded again.
+pub fn save_scene(scene: &mut Scene, time: f32, path: &str) -> Result<(), String> {
+    animate(&mut scene.objects, &scene.animations, time, 0.0);
+    let json = serde_json::to_string_pretty(scene).map_err(|e| e.to_string());
+    animate(&mut scene.objects, &scene.animations, 0.0, time);
+
+    std::fs::write(path, json?).map_err(|e| format!("{path}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(vertex1: Vec3, vertex2: Vec3, vertex3: Vec3) -> Object {
+        Object::Triangle(Triangle {
+            vertex1,
+            vertex2,
+            vertex3,
+            normals: None,
+            texture: None,
+            emission: 0.0,
+        })
+    }
+
+    fn normals(object: &Object) -> [Vec3; 3] {
+        match object {
+            Object::Triangle(triangle) => triangle.normals.unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    // Two faces folded along the edge from the origin to (0, 0, 1), rising
+    // `height` over a unit of run on either side.
+    fn fold(height: f32) -> Vec<Object> {
+        vec![
+            triangle(Vec3::ZERO, Vec3::Z, Vec3::new(1.0, height, 0.0)),
+            triangle(Vec3::Z, Vec3::ZERO, Vec3::new(-1.0, height, 0.0)),
+        ]
+    }
+
+    #[test]
+    fn shallow_folds_share_normals_along_the_edge() {
+        let mut objects = fold(0.5);
+        smooth_normals(&mut objects);
+
+        let [edge, _, outer] = normals(&objects[0]);
+        assert!(edge.abs_diff_eq(Vec3::Y, 1e-5));
+        assert!(edge.abs_diff_eq(normals(&objects[1])[1], 1e-5));
+        assert!(outer.x < 0.0);
+    }
+
+    #[test]
+    fn creases_keep_their_hard_edge() {
+        let mut objects = fold(2.0);
+        smooth_normals(&mut objects);
+
+        let [edge, _, outer] = normals(&objects[0]);
+        assert!(edge.abs_diff_eq(outer, 1e-5));
+    }
+
+    #[test]
+    fn noise_settings_from_scene_files_are_clamped() {
+        let map = NoiseMap::from(Noise {
+            seed: 1,
+            resolution: 8,
+            frequency: 4.0,
+            octaves: 64,
+        });
+        assert_eq!(map.noise.octaves, MAX_NOISE_OCTAVES);
+        assert!(map.heights.iter().all(|h| h.is_finite()));
+
+        let map = NoiseMap::from(Noise {
+            resolution: 1,
+            ..map.noise
+        });
+        assert_eq!(map.heights.len(), 4);
+    }
+
+    fn sdf_sphere(x: f32) -> Box<Sdf> {
+        Box::new(Sdf::Sphere {
+            center: Vec3::new(x, 0.0, 0.0),
+            radius: 1.0,
+        })
+    }
+
+    #[test]
+    fn smooth_unions_without_a_radius_are_plain_unions() {
+        let sharp = Sdf::SmoothUnion(sdf_sphere(-1.0), sdf_sphere(1.0), 0.0);
+        for p in [
+            Vec3::ZERO,
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 3.0, 0.0),
+        ] {
+            let union = sdf_sphere(-1.0)
+                .distance(p)
+                .min(sdf_sphere(1.0).distance(p));
+            assert_eq!(sharp.distance(p), union);
+        }
+
+        // Blending fills in the dip between the two spheres.
+        let smooth = Sdf::SmoothUnion(sdf_sphere(-1.0), sdf_sphere(1.0), 0.5);
+        assert!(smooth.distance(Vec3::Y) < sharp.distance(Vec3::Y));
+    }
+
+    fn ball(x: f32) -> Object {
+        Object::Sphere(Sphere {
+            center: Vec3::new(x, 0.0, 0.0),
+            radius: 0.5,
+            texture: None,
+            emission: 0.0,
+        })
+    }
+
+    // Three balls: the first spins, the second falls and the third does
+    // neither.
+    fn moving_scene() -> Scene {
+        Scene {
+            objects: vec![ball(0.0), ball(2.0), ball(4.0)],
+            meshes: Vec::new(),
+            animations: vec![Animation {
+                object: 0,
+                motion: Motion::Spin {
+                    axis: Vec3::Y,
+                    period: 1.0,
+                },
+            }],
+            bodies: vec![Body {
+                object: 1,
+                velocity: Vec3::ZERO,
+            }],
+            background: None,
+            bookmarks: Vec::new(),
+            cameras: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn stepping_gives_moved_objects_before_and_after() {
+        let mut scene = moving_scene();
+        let mut moved = Vec::new();
+        step_scene(&mut scene, 0.0, 0.1, |object| moved.push(object.origin()));
+
+        assert_eq!(moved.len(), 4);
+        assert_eq!(moved[0], Vec3::ZERO);
+        assert_eq!(moved[2], Vec3::new(2.0, 0.0, 0.0));
+        assert!(moved[3].y < 0.0);
+        assert_eq!(scene.objects[2].origin(), Vec3::new(4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn bodies_at_rest_are_not_given() {
+        let mut scene = moving_scene();
+        scene.animations.clear();
+        let mut moved = 0;
+        step_scene(&mut scene, 1.0, 1.0, |_| moved += 1);
+        assert_eq!(moved, 0);
+    }
+
+    #[test]
+    fn removing_an_object_keeps_the_others_animated() {
+        let mut scene = moving_scene();
+        scene.bodies[0].object = 2;
+        remove_object(&mut scene, 1);
+
+        assert_eq!(scene.objects.len(), 2);
+        assert_eq!(scene.animations[0].object, 0);
+        assert_eq!(scene.bodies[0].object, 1);
+
+        remove_object(&mut scene, 0);
+        assert!(scene.animations.is_empty());
+        assert_eq!(scene.bodies[0].object, 0);
+    }
+}
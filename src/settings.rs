@@ -0,0 +1,593 @@
+use crate::asciicast::AsciiFormat;
+use crate::camera::{Projection, DEFAULT_FOV};
+use crate::generate::Generator;
+use crate::lighting::{Lighting, MAX_BOUNCES};
+use crate::render::{HEIGHT, WIDTH};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ramp {
+    Classic,
+    Simple,
+    Extended,
+    Blocks,
+    Braille,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    // One ray per cell, shaded with the selected character ramp.
+    Ramp,
+    // Eight rays per cell, packed into the dots of a braille character.
+    Braille,
+}
+
+// What the ramp shows in place of the lit surface, for looking into why a
+// frame looks the way it does.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DebugView {
+    Shaded,
+    // Distances up to DEPTH_RANGE, from dense (near) to sparse (far).
+    Depth,
+    // One component of the surface normal, from -1 (darkest) to 1.
+    NormalX,
+    NormalY,
+    NormalZ,
+    // Texture coordinates, on the primitives that have them.
+    U,
+    V,
+    // Every object index in a different brightness.
+    Object,
+    // Hits at the brightest character and misses at the darkest.
+    Hits,
+    // Intersection tests and march steps taken by each ray, on a log scale.
+    Iterations,
+}
+
+pub const DEBUG_VIEWS: [DebugView; 10] = [
+    DebugView::Shaded,
+    DebugView::Depth,
+    DebugView::NormalX,
+    DebugView::NormalY,
+    DebugView::NormalZ,
+    DebugView::U,
+    DebugView::V,
+    DebugView::Object,
+    DebugView::Hits,
+    DebugView::Iterations,
+];
+
+impl DebugView {
+    pub fn parse(name: &str) -> Option<Self> {
+        DEBUG_VIEWS.into_iter().find(|v| v.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::Shaded => "shaded",
+            DebugView::Depth => "depth",
+            DebugView::NormalX => "normal-x",
+            DebugView::NormalY => "normal-y",
+            DebugView::NormalZ => "normal-z",
+            DebugView::U => "u",
+            DebugView::V => "v",
+            DebugView::Object => "object",
+            DebugView::Hits => "hits",
+            DebugView::Iterations => "iterations",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let i = DEBUG_VIEWS.iter().position(|v| *v == self).unwrap();
+        DEBUG_VIEWS[(i + 1) % DEBUG_VIEWS.len()]
+    }
+}
+
+pub const RAMPS: [Ramp; 5] = [
+    Ramp::Classic,
+    Ramp::Simple,
+    Ramp::Extended,
+    Ramp::Blocks,
+    Ramp::Braille,
+];
+
+impl Ramp {
+    // Characters from darkest to brightest. A miss is always drawn as ' '.
+    pub fn chars(self) -> &'static [char] {
+        match self {
+            Ramp::Classic => &[
+                '.', ',', ':', ';', '*', '+', 'o', 'x', '%', '&', '#', '$', '@', '9',
+            ],
+            Ramp::Simple => &['.', ':', '-', '=', '+', '*', '#', '%', '@'],
+            Ramp::Extended => &[
+                '.', '\'', '`', '^', '"', ',', ':', ';', 'I', 'l', '!', 'i', '>', '<', '~', '+',
+                '_', '-', '?', ']', '[', '}', '{', '1', ')', '(', '|', '\\', '/', 't', 'f', 'j',
+                'r', 'x', 'n', 'u', 'v', 'c', 'z', 'X', 'Y', 'U', 'J', 'C', 'L', 'Q', '0', 'O',
+                'Z', 'm', 'w', 'q', 'p', 'd', 'b', 'k', 'h', 'a', 'o', '*', '#', 'M', 'W', '&',
+                '8', '%', 'B', '@', '$',
+            ],
+            Ramp::Blocks => &['░', '▒', '▓', '█'],
+            Ramp::Braille => &['⠁', '⠃', '⠇', '⡇', '⡏', '⡟', '⡿', '⣿'],
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Ramp> {
+        RAMPS.into_iter().find(|ramp| ramp.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Ramp::Classic => "classic",
+            Ramp::Simple => "simple",
+            Ramp::Extended => "extended",
+            Ramp::Blocks => "blocks",
+            Ramp::Braille => "braille",
+        }
+    }
+
+    pub fn next(self) -> Ramp {
+        let i = RAMPS.iter().position(|ramp| *ramp == self).unwrap();
+        RAMPS[(i + 1) % RAMPS.len()]
+    }
+}
+
+// How the camera moves through the scene.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Movement {
+    // Straight through anything, as it always used to.
+    Noclip,
+    // Freely in every direction, but stopping and sliding along surfaces.
+    Fly,
+    // Level, held at eye height above the ground and climbing low steps.
+    Walk,
+}
+
+pub const MOVEMENTS: [Movement; 3] = [Movement::Noclip, Movement::Fly, Movement::Walk];
+
+impl Movement {
+    pub fn parse(name: &str) -> Option<Self> {
+        MOVEMENTS.into_iter().find(|m| m.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Movement::Noclip => "noclip",
+            Movement::Fly => "fly",
+            Movement::Walk => "walk",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let i = MOVEMENTS.iter().position(|m| *m == self).unwrap();
+        MOVEMENTS[(i + 1) % MOVEMENTS.len()]
+    }
+}
+
+// How the grid is shared between the active camera and a second one.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ViewLayout {
+    // The active camera fills the grid.
+    Single,
+    // The second camera in a small view in the top right corner.
+    Inset,
+    // The active camera on the left half and the second on the right.
+    Split,
+}
+
+pub const VIEW_LAYOUTS: [ViewLayout; 3] =
+    [ViewLayout::Single, ViewLayout::Inset, ViewLayout::Split];
+
+impl ViewLayout {
+    pub fn parse(name: &str) -> Option<Self> {
+        VIEW_LAYOUTS.into_iter().find(|l| l.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ViewLayout::Single => "single",
+            ViewLayout::Inset => "inset",
+            ViewLayout::Split => "split",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let i = VIEW_LAYOUTS.iter().position(|l| *l == self).unwrap();
+        VIEW_LAYOUTS[(i + 1) % VIEW_LAYOUTS.len()]
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+// The live render and control options. They start out as given on the
+// command line, and the settings window and function keys change them as the
+// program runs.
+#[derive(Clone)]
+pub struct Settings {
+    pub scene: Option<String>,
+    // Builds the scene procedurally instead, from a seed and object count.
+    pub generate: Option<Generator>,
+    pub seed: u64,
+    pub object_count: usize,
+    pub camera_path: Option<String>,
+    pub frames: usize,
+    // Input sessions to record to or replay from, and whether replayed frames
+    // are also written to `output`.
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub export: bool,
+    pub output: String,
+    // A file every character frame is also written to, with its time.
+    pub ascii: Option<String>,
+    pub ascii_format: AsciiFormat,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub image_samples: usize,
+    pub image_format: ImageFormat,
+    pub ramp: Ramp,
+    pub gamma: f32,
+    pub mode: RenderMode,
+    pub debug_view: DebugView,
+    pub samples: usize,
+    // Cells per traced ray along each side of the grid: at 2 one cell in each
+    // 2x2 block is traced and copied across the block. Lowered and raised
+    // again to try to keep the frame rate at `target_fps`, unless that's zero.
+    pub resolution: usize,
+    pub target_fps: f32,
+    pub layout: ViewLayout,
+    pub reproject: bool,
+    pub quality: bool,
+    pub fov: f32,
+    // Width over height of the viewport; derived from the grid or the image
+    // being rendered when unset.
+    pub aspect: Option<f32>,
+    pub aperture: f32,
+    pub focus_distance: f32,
+    pub projection: Projection,
+    pub fog_enabled: bool,
+    pub fog_density: f32,
+    pub deadzone: f32,
+    pub gamepad_sensitivity: f32,
+    pub movement: Movement,
+    pub move_speed: f32,
+    pub look_speed: f32,
+    pub lighting: Lighting,
+    pub max_bounces: usize,
+    // Shadow and bounce rays a path traced frame may cast between all its
+    // paths, or zero for no limit.
+    pub ray_budget: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn command_line() -> Vec<String> {
+    std::env::args().skip(1).collect()
+}
+
+// Browsers have no command line, so the page's query string stands in for
+// one: `?scene=scenes/sky.json&braille` reads as `--scene scenes/sky.json
+// --braille`.
+#[cfg(target_arch = "wasm32")]
+fn command_line() -> Vec<String> {
+    use js_sys::{decode_uri_component, global, JsString, Reflect};
+
+    let search = Reflect::get(&global(), &JsString::from("location"))
+        .and_then(|location| Reflect::get(&location, &JsString::from("search")))
+        .ok()
+        .and_then(|search| search.as_string())
+        .unwrap_or_default();
+
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .flat_map(|pair| {
+            let (key, value) = match pair.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (pair, None),
+            };
+            let decode =
+                |s: &str| decode_uri_component(s).map_or(s.to_string(), |decoded| decoded.into());
+            std::iter::once(format!("--{}", decode(key))).chain(value.map(decode))
+        })
+        .collect()
+}
+
+// Fog density used when fog is toggled on without one being given.
+pub const FOG_DENSITY: f32 = 0.15;
+
+// Rays traced per character cell in the interactive view.
+pub const SAMPLE_COUNTS: [usize; 4] = [1, 2, 4, 8];
+
+// Every resolution the grid can be traced at, finest first. Each divides the
+// tile size, so blocks never straddle tiles.
+pub const RESOLUTIONS: [usize; 3] = [1, 2, 4];
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            scene: None,
+            generate: None,
+            seed: 1,
+            object_count: 1000,
+            camera_path: None,
+            frames: 60,
+            record: None,
+            replay: None,
+            export: false,
+            output: String::from("frames"),
+            ascii: None,
+            ascii_format: AsciiFormat::Cast,
+            image_width: WIDTH,
+            image_height: HEIGHT,
+            image_samples: 2,
+            image_format: ImageFormat::Png,
+            ramp: Ramp::Classic,
+            gamma: 1.0,
+            mode: RenderMode::Ramp,
+            debug_view: DebugView::Shaded,
+            samples: 1,
+            resolution: 1,
+            target_fps: 30.0,
+            layout: ViewLayout::Single,
+            reproject: false,
+            quality: false,
+            fov: DEFAULT_FOV,
+            aspect: None,
+            aperture: 0.0,
+            focus_distance: 3.0,
+            projection: Projection::Perspective,
+            fog_enabled: false,
+            fog_density: FOG_DENSITY,
+            deadzone: 0.15,
+            gamepad_sensitivity: 1.0,
+            movement: Movement::Noclip,
+            move_speed: 0.05,
+            look_speed: 0.025,
+            lighting: Lighting {
+                ambient: 0.2,
+                diffuse: 0.6,
+            },
+            max_bounces: MAX_BOUNCES,
+            ray_budget: 0,
+        }
+    }
+}
+
+impl Settings {
+    pub fn from_args() -> Result<Self, String> {
+        let mut settings = Settings::default();
+
+        let mut args = command_line().into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+
+            match arg.as_str() {
+                "--scene" => settings.scene = Some(value()?),
+                "--generate" => {
+                    let value = value()?;
+                    settings.generate =
+                        Some(Generator::parse(&value).ok_or(format!("unknown generator {value}"))?);
+                }
+                "--seed" => {
+                    let value = value()?;
+                    settings.seed = value.parse().map_err(|_| format!("invalid seed {value}"))?;
+                }
+                "--objects" => {
+                    let value = value()?;
+                    settings.object_count = value
+                        .parse()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or(format!("invalid object count {value}"))?;
+                }
+                "--camera-path" => settings.camera_path = Some(value()?),
+                "--frames" => {
+                    let value = value()?;
+                    settings.frames = value
+                        .parse()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or(format!("invalid frame count {value}"))?;
+                }
+                "--output" => settings.output = value()?,
+                "--ray-budget" => {
+                    let value = value()?;
+                    settings.ray_budget = value
+                        .parse()
+                        .map_err(|_| format!("invalid ray budget {value}"))?;
+                }
+                "--record" => settings.record = Some(value()?),
+                "--replay" => settings.replay = Some(value()?),
+                "--export" => settings.export = true,
+                "--ascii" => settings.ascii = Some(value()?),
+                "--ascii-format" => {
+                    let value = value()?;
+                    settings.ascii_format = AsciiFormat::parse(&value)
+                        .ok_or(format!("unknown ascii format {value}"))?;
+                }
+                "--image-size" => {
+                    let value = value()?;
+                    let (width, height) = value
+                        .split_once('x')
+                        .and_then(|(w, h)| {
+                            let width = w.parse().ok().filter(|n| *n > 0)?;
+                            let height = h.parse().ok().filter(|n| *n > 0)?;
+                            Some((width, height))
+                        })
+                        .ok_or(format!("invalid image size {value}, expected WxH"))?;
+                    settings.image_width = width;
+                    settings.image_height = height;
+                }
+                "--image-samples" => {
+                    let value = value()?;
+                    settings.image_samples = value
+                        .parse()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or(format!("invalid image sample count {value}"))?;
+                }
+                "--image-format" => {
+                    settings.image_format = match value()?.as_str() {
+                        "png" => ImageFormat::Png,
+                        "ppm" => ImageFormat::Ppm,
+                        other => return Err(format!("unknown image format {other}")),
+                    };
+                }
+                "--ramp" => {
+                    let value = value()?;
+                    settings.ramp =
+                        Ramp::parse(&value).ok_or(format!("unknown character ramp {value}"))?;
+                }
+                "--braille" => settings.mode = RenderMode::Braille,
+                "--debug-view" => {
+                    let value = value()?;
+                    settings.debug_view =
+                        DebugView::parse(&value).ok_or(format!("unknown debug view {value}"))?;
+                }
+                "--reproject" => settings.reproject = true,
+                "--quality" => settings.quality = true,
+                "--projection" => {
+                    let value = value()?;
+                    settings.projection =
+                        Projection::parse(&value).ok_or(format!("unknown projection {value}"))?;
+                }
+                "--fog" => {
+                    let value = value()?;
+                    settings.fog_density = value
+                        .parse()
+                        .ok()
+                        .filter(|d: &f32| *d >= 0.0)
+                        .ok_or(format!("invalid fog density {value}"))?;
+                    settings.fog_enabled = true;
+                }
+                "--deadzone" => {
+                    let value = value()?;
+                    settings.deadzone = value
+                        .parse()
+                        .ok()
+                        .filter(|d: &f32| (0.0..1.0).contains(d))
+                        .ok_or(format!("invalid deadzone {value}"))?;
+                }
+                "--gamepad-sensitivity" => {
+                    let value = value()?;
+                    settings.gamepad_sensitivity = value
+                        .parse()
+                        .ok()
+                        .filter(|s: &f32| *s > 0.0)
+                        .ok_or(format!("invalid gamepad sensitivity {value}"))?;
+                }
+                "--layout" => {
+                    let value = value()?;
+                    settings.layout =
+                        ViewLayout::parse(&value).ok_or(format!("unknown layout {value}"))?;
+                }
+                "--movement" => {
+                    let value = value()?;
+                    settings.movement =
+                        Movement::parse(&value).ok_or(format!("unknown movement {value}"))?;
+                }
+                "--fov" => {
+                    let value = value()?;
+                    settings.fov = value
+                        .parse()
+                        .ok()
+                        .filter(|f: &f32| 0.0 < *f && *f < 180.0)
+                        .ok_or(format!("invalid field of view {value}"))?;
+                }
+                "--aspect" => {
+                    let value = value()?;
+                    settings.aspect = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|a: &f32| *a > 0.0)
+                            .ok_or(format!("invalid aspect {value}"))?,
+                    );
+                }
+                "--aperture" => {
+                    let value = value()?;
+                    settings.aperture = value
+                        .parse()
+                        .ok()
+                        .filter(|a: &f32| *a >= 0.0)
+                        .ok_or(format!("invalid aperture {value}"))?;
+                }
+                "--focus-distance" => {
+                    let value = value()?;
+                    settings.focus_distance = value
+                        .parse()
+                        .ok()
+                        .filter(|d: &f32| *d > 0.0)
+                        .ok_or(format!("invalid focus distance {value}"))?;
+                }
+                "--samples" => {
+                    let value = value()?;
+                    settings.samples = value
+                        .parse()
+                        .ok()
+                        .filter(|n| SAMPLE_COUNTS.contains(n))
+                        .ok_or(format!(
+                            "invalid sample count {value}, expected 1, 2, 4 or 8"
+                        ))?;
+                }
+                "--resolution" => {
+                    let value = value()?;
+                    settings.resolution = value
+                        .parse()
+                        .ok()
+                        .filter(|n| RESOLUTIONS.contains(n))
+                        .ok_or(format!("invalid resolution {value}, expected 1, 2 or 4"))?;
+                }
+                "--target-fps" => {
+                    let value = value()?;
+                    settings.target_fps = value
+                        .parse()
+                        .ok()
+                        .filter(|f: &f32| *f >= 0.0)
+                        .ok_or(format!("invalid target frame rate {value}"))?;
+                }
+                "--gamma" => {
+                    let value = value()?;
+                    settings.gamma = value
+                        .parse()
+                        .ok()
+                        .filter(|g: &f32| *g > 0.0)
+                        .ok_or(format!("invalid gamma {value}"))?;
+                }
+                _ => return Err(format!("unknown argument {arg}")),
+            }
+        }
+
+        if settings.scene.is_some() && settings.generate.is_some() {
+            return Err(String::from(
+                "--scene and --generate can't be used together",
+            ));
+        }
+        if settings.record.is_some() && settings.replay.is_some() {
+            return Err(String::from("--record and --replay can't be used together"));
+        }
+        if settings.export && settings.replay.is_none() {
+            return Err(String::from("--export needs a session to --replay"));
+        }
+
+        Ok(settings)
+    }
+
+    // Whether frames are path traced a pass at a time rather than traced
+    // directly. Braille cells have no single intensity to accumulate, and
+    // debug views show what the first hit is.
+    pub fn path_traced(&self) -> bool {
+        self.quality && self.mode == RenderMode::Ramp && self.debug_view == DebugView::Shaded
+    }
+
+    // The fog density to trace with, zero when fog is off.
+    pub fn fog(&self) -> f32 {
+        if self.fog_enabled {
+            self.fog_density
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use notan::utils::Instant;
+use std::time::{Duration, SystemTime};
+
+// How often the file is looked at. Checking costs a stat call, so a few
+// times a second is plenty to feel immediate after saving.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Notices when a file is written, by polling its modification time. Files
+// that can't be read, like the built in scenes on the web, never change.
+// Polling from update() rather than using notify works the same on every
+// platform, with no watcher thread to hand events back from and no extra
+// dependency; notify isn't among the crates this builds from.
+pub struct FileWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(path: &str) -> Self {
+        FileWatcher {
+            path: path.to_string(),
+            modified: modified(path),
+            checked: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // True once for each time the file changes, at most every POLL_INTERVAL.
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.checked = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,86 @@
+// Renders fixed scenes to character grids and compares them against the
+// references checked in under tests/golden. After a change that's meant to
+// alter the output, look over the new frames and accept them with
+// `UPDATE_GOLDEN=1 cargo test --test golden`.
+
+use cast::camera::Camera;
+use cast::render::{Renderer, COLS};
+use cast::scene::{default_scene, Scene};
+use cast::settings::{RenderMode, Settings};
+use std::path::Path;
+
+fn default() -> Scene {
+    Scene {
+        objects: default_scene(),
+        meshes: Vec::new(),
+        animations: Vec::new(),
+        bodies: Vec::new(),
+        background: None,
+        bookmarks: Vec::new(),
+        cameras: Vec::new(),
+    }
+}
+
+// The frame as text, top row first.
+fn render(scene: &Scene, settings: Settings) -> String {
+    let camera = Camera::new(&settings);
+    let mut renderer = Renderer::new(settings);
+    let frame = renderer.render_frame(scene, &camera);
+
+    frame
+        .cells
+        .chunks(COLS)
+        .rev()
+        .map(|row| row.iter().collect::<String>() + "\n")
+        .collect()
+}
+
+fn check(name: &str, frame: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.txt"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, frame).unwrap();
+        return;
+    }
+
+    let expected =
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    let differences: Vec<usize> = expected
+        .lines()
+        .zip(frame.lines())
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(row, _)| row)
+        .collect();
+
+    assert!(
+        differences.is_empty() && expected.lines().count() == frame.lines().count(),
+        "{name} differs from {} on rows {differences:?}\n{frame}",
+        path.display(),
+    );
+}
+
+#[test]
+fn default_scene_ramp() {
+    check("default_scene", &render(&default(), Settings::default()));
+}
+
+#[test]
+fn default_scene_braille() {
+    let settings = Settings {
+        mode: RenderMode::Braille,
+        ..Settings::default()
+    };
+    check("default_scene_braille", &render(&default(), settings));
+}
+
+#[test]
+fn default_scene_four_samples() {
+    let settings = Settings {
+        samples: 4,
+        ..Settings::default()
+    };
+    check("default_scene_4_samples", &render(&default(), settings));
+}